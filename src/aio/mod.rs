@@ -0,0 +1,282 @@
+//! Tokio front-end for the driver, enabled by the `async` feature.
+//!
+//! [`AsyncMtrf`] runs the same request/response correlation as
+//! [`Mtrf`](crate::mtrf::Mtrf), but on a tokio task that wakes up on I/O
+//! readiness instead of polling the port.
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::cmd::decoder::FrameDecoder;
+use crate::cmd::request::Request;
+use crate::cmd::response::Response;
+use crate::cmd::MESSAGE_LENGTH;
+use crate::error::RequestError;
+use crate::mtrf::{check_ctr, guard, DEFAULT_TIMEOUT};
+use crate::pending::{PendingKey, PendingTable};
+
+#[cfg(unix)]
+pub mod serial;
+
+#[cfg(unix)]
+pub use self::serial::AsyncSerial;
+
+/// Unsolicited messages buffered per subscriber before the oldest are dropped.
+const EVENTS_CAPACITY: usize = 64;
+
+/// Byte stream to the adapter, e.g. [`AsyncSerial`] or a `TcpStream`.
+pub trait AsyncTransport: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> AsyncTransport for T {}
+
+/// Async handle to the adapter. Clones share the same worker task.
+#[derive(Clone)]
+pub struct AsyncMtrf {
+    req_tx: mpsc::UnboundedSender<Request>,
+    pending: Arc<Mutex<PendingTable<oneshot::Sender<Response>>>>,
+    events: broadcast::Sender<Response>,
+    seq: Arc<AtomicU64>,
+    timeout: Duration,
+}
+
+impl AsyncMtrf {
+    #[cfg(unix)]
+    pub fn open_serial(port_name: &str) -> Result<AsyncMtrf, Error> {
+        Ok(Self::with_transport(AsyncSerial::open(port_name)?))
+    }
+
+    pub async fn connect_tcp<A: ToSocketAddrs>(addr: A) -> Result<AsyncMtrf, Error> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(Self::with_transport(stream))
+    }
+
+    /// Spawns the worker on the current tokio runtime.
+    pub fn with_transport<T: AsyncTransport>(transport: T) -> AsyncMtrf {
+        let (req_tx, req_rx) = mpsc::unbounded_channel();
+        let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+        let pending = Arc::new(Mutex::new(PendingTable::default()));
+        tokio::spawn(run_loop(transport, req_rx, pending.clone(), events.clone()));
+        AsyncMtrf {
+            req_tx,
+            pending,
+            events,
+            seq: Arc::new(AtomicU64::new(0)),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub async fn send(&self, req: Request) -> Result<(), RequestError> {
+        guard(&req)?;
+        self.req_tx
+            .send(req)
+            .map_err(|_| RequestError::TransportClosed)
+    }
+
+    /// Sends the request and waits for the response using the default timeout.
+    pub async fn request(&self, req: Request) -> Result<Response, RequestError> {
+        self.request_timeout(req, self.timeout).await
+    }
+
+    pub async fn request_timeout(
+        &self,
+        req: Request,
+        timeout: Duration,
+    ) -> Result<Response, RequestError> {
+        guard(&req)?;
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(seq, PendingKey::of(&req), reply_tx);
+        if self.req_tx.send(req).is_err() {
+            self.pending.lock().unwrap().remove(seq);
+            return Err(RequestError::TransportClosed);
+        }
+
+        let resp = match tokio::time::timeout(timeout, reply).await {
+            Ok(Ok(resp)) => resp,
+            Ok(Err(_)) => return Err(RequestError::TransportClosed),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(seq);
+                return Err(RequestError::Timeout);
+            }
+        };
+        check_ctr(resp)
+    }
+
+    pub fn default_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the timeout of `request` for this handle. Clones keep their own value.
+    pub fn set_default_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Messages that did not answer a request, from the moment of the call.
+    /// A subscriber that falls behind loses the oldest messages.
+    pub fn messages(&self) -> impl Stream<Item = Response> {
+        BroadcastStream::new(self.events.subscribe()).filter_map(|msg| msg.ok())
+    }
+}
+
+async fn run_loop<T: AsyncTransport>(
+    mut transport: T,
+    mut req_rx: mpsc::UnboundedReceiver<Request>,
+    pending: Arc<Mutex<PendingTable<oneshot::Sender<Response>>>>,
+    events: broadcast::Sender<Response>,
+) {
+    let mut decoder = FrameDecoder::new();
+    let mut buf = [0; MESSAGE_LENGTH];
+    loop {
+        tokio::select! {
+            req = req_rx.recv() => {
+                let req = match req {
+                    Some(req) => req,
+                    None => {
+                        info!("Request channel disconnected");
+                        break;
+                    }
+                };
+                debug!("Write request {}", req);
+                if let Err(err) = transport.write_all(&req.to_message()).await {
+                    warn!("Failed to write request {}", err);
+                    break;
+                }
+            }
+            count = transport.read(&mut buf) => {
+                let count = match count {
+                    Ok(0) => {
+                        error!("Failed to read response. Transport closed");
+                        break;
+                    }
+                    Ok(count) => count,
+                    Err(err) => {
+                        error!("Failed to read response. {}", err);
+                        break;
+                    }
+                };
+                decoder.push(&buf[..count]);
+                while let Some(msg) = decoder.next_frame() {
+                    match Response::try_from(msg) {
+                        Ok(resp) => {
+                            debug!("Receive msg:{:?}", resp);
+                            let reply = pending.lock().unwrap().take(&resp);
+                            let unanswered = match reply {
+                                Some(reply) => reply.send(resp).is_err(),
+                                None => true,
+                            };
+                            if unanswered {
+                                // Nobody listening is fine.
+                                let _ = events.send(resp);
+                            }
+                        }
+                        Err(err) => {
+                            warn!("Failed to decode response {}: msg:[{:?}]", err, msg);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    pending.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio_stream::StreamExt;
+
+    use crate::aio::AsyncMtrf;
+    use crate::cmd::request::{bind, Request};
+    use crate::cmd::{Cmd, CtrRequest, CtrResponse, Mode};
+    use crate::emulator::{Emulator, SimDevice};
+    use crate::error::RequestError;
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn request_and_stream_over_pty() {
+        let emulator = Emulator::new();
+        emulator.add_device(SimDevice::new(0xAB));
+        let pty = emulator.attach_pty().unwrap();
+        let mtrf = AsyncMtrf::open_serial(pty.path().to_str().unwrap()).unwrap();
+
+        let resp = mtrf.request(bind(Mode::TxF, 7)).await.unwrap();
+        assert_eq!(resp.id, 0xAB);
+
+        let on = Request {
+            mode: Mode::TxF,
+            ch: 7,
+            cmd: Cmd::On,
+            ..Default::default()
+        };
+        assert_eq!(mtrf.request(on).await.unwrap().ctr, CtrResponse::Success);
+        assert!(emulator.device(0xAB).unwrap().on);
+
+        let remote = emulator.clone();
+        let presser = tokio::task::spawn_blocking(move || {
+            while remote.bind_mode().is_none() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            remote.press(Mode::RX, 5, Cmd::Bind);
+        });
+        let resp = mtrf.request(bind(Mode::RX, 2)).await.unwrap();
+        presser.await.unwrap();
+        assert_eq!(resp.ctr, CtrResponse::BindSuccess);
+
+        let mut messages = mtrf.messages();
+        emulator.press(Mode::RX, 5, Cmd::Switch);
+        let msg = tokio::time::timeout(Duration::from_secs(1), messages.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.ch, 2);
+        assert_eq!(msg.cmd.as_u8(), Cmd::Switch.as_u8());
+    }
+
+    #[tokio::test]
+    pub async fn rejects_unconfirmed_clear() {
+        let (host, _adapter) = tokio::io::duplex(64);
+        let mtrf = AsyncMtrf::with_transport(host);
+        let raw = Request {
+            mode: Mode::TxF,
+            ctr: CtrRequest::ClearChannel,
+            ..Default::default()
+        };
+        match mtrf.request(raw).await {
+            Err(RequestError::InvalidRequest(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let raw = Request {
+            ctr: CtrRequest::ClearMemory,
+            ..raw
+        };
+        match mtrf.send(raw).await {
+            Err(RequestError::InvalidRequest(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    pub async fn closed_transport() {
+        let (host, adapter) = tokio::io::duplex(64);
+        let mtrf = AsyncMtrf::with_transport(host);
+        drop(adapter);
+        match mtrf.request(bind(Mode::TxF, 0)).await {
+            Err(RequestError::TransportClosed) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ::serial::{SerialPort, SystemPort};
+use anyhow::Error;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::transport::serial::SETTINGS;
+
+/// Non-blocking serial port driven by the tokio reactor.
+pub struct AsyncSerial {
+    port: AsyncFd<SystemPort>,
+}
+
+impl AsyncSerial {
+    /// Opens and configures the port. Must be called inside a tokio runtime.
+    pub fn open(port_name: &str) -> Result<AsyncSerial, Error> {
+        let mut port = ::serial::open(port_name)?;
+        port.configure(&SETTINGS)?;
+        let fd = port.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            ensure!(
+                flags >= 0 && libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) >= 0,
+                "Failed to make {} non-blocking: {}",
+                port_name,
+                io::Error::last_os_error()
+            );
+        }
+        Ok(AsyncSerial {
+            port: AsyncFd::new(port)?,
+        })
+    }
+}
+
+impl AsyncRead for AsyncSerial {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = match self.port.poll_read_ready(cx) {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+            let unfilled = buf.initialize_unfilled();
+            let res = guard.try_io(|port| {
+                let count = unsafe {
+                    libc::read(
+                        port.as_raw_fd(),
+                        unfilled.as_mut_ptr() as *mut libc::c_void,
+                        unfilled.len(),
+                    )
+                };
+                if count < 0 {
+                    Err(io::Error::last_os_error())
+                } else if count == 0 && !unfilled.is_empty() {
+                    // The port is configured with VMIN = 0, so an empty read
+                    // means "no data yet" rather than end of file.
+                    Err(io::ErrorKind::WouldBlock.into())
+                } else {
+                    Ok(count as usize)
+                }
+            });
+            match res {
+                Ok(Ok(count)) => {
+                    buf.advance(count);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(err)) => return Poll::Ready(Err(err)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for AsyncSerial {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = match self.port.poll_write_ready(cx) {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+            let res = guard.try_io(|port| {
+                let count = unsafe {
+                    libc::write(
+                        port.as_raw_fd(),
+                        data.as_ptr() as *const libc::c_void,
+                        data.len(),
+                    )
+                };
+                if count < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(count as usize)
+                }
+            });
+            match res {
+                Ok(res) => return Poll::Ready(res),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
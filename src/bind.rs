@@ -0,0 +1,288 @@
+//! Guided binding of a device or remote to an adapter channel.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bus::Filter;
+use crate::cmd::request::{bind, Request};
+use crate::cmd::response::Response;
+use crate::cmd::{CtrRequest, CtrResponse, Mode, CHANNELS};
+use crate::error::RequestError;
+use crate::mtrf::Mtrf;
+use crate::registry::Registry;
+
+/// Pause between bind attempts while no TX-F device is in service mode.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Device captured by a [`BindSession`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BoundDevice {
+    pub mode: Mode,
+    pub ch: u8,
+    /// Address of a nooLite-F device or remote, `None` in the nooLite modes.
+    pub id: Option<u32>,
+    /// The bind request and its confirmation, for [`Registry::record_bind`].
+    pub request: Request,
+    pub response: Response,
+}
+
+/// Binds one device to a channel.
+///
+/// - TX: sends the bind command, the receiver must be in service mode. There
+///   is no feedback, the first `Success` completes the session.
+/// - TX-F: repeats the bind command until a device in service mode answers.
+/// - RX/RX-F: opens bind mode and waits for a remote to send its bind command.
+///
+/// Clones share the session, so another thread can [`cancel`](BindSession::cancel) it.
+#[derive(Clone)]
+pub struct BindSession {
+    mtrf: Mtrf,
+    mode: Mode,
+    ch: u8,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl BindSession {
+    pub fn new(mtrf: Mtrf, mode: Mode, ch: u8) -> Result<BindSession, RequestError> {
+        match mode {
+            Mode::TX | Mode::RX | Mode::TxF | Mode::RxF => {}
+            _ => {
+                return Err(RequestError::InvalidRequest(
+                    "Binding needs a TX or RX mode",
+                ))
+            }
+        }
+        if ch >= CHANNELS {
+            return Err(RequestError::InvalidRequest(
+                "The ch value must be between 0 and 63",
+            ));
+        }
+        Ok(BindSession {
+            mtrf,
+            mode,
+            ch,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Session on the first channel of `mode` with nothing in the registry.
+    pub fn on_free_channel(
+        mtrf: Mtrf,
+        mode: Mode,
+        registry: &Registry,
+    ) -> Result<BindSession, RequestError> {
+        match registry.free_channel(mode) {
+            Some(ch) => BindSession::new(mtrf, mode, ch),
+            None => Err(RequestError::InvalidRequest("No free channel left")),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn ch(&self) -> u8 {
+        self.ch
+    }
+
+    /// Enters bind mode and waits up to `timeout` for the device.
+    pub fn run(&self, timeout: Duration) -> Result<BoundDevice, RequestError> {
+        let req = bind(self.mode, self.ch);
+        let resp = match self.mode {
+            Mode::TxF => self.retry(req, timeout)?,
+            Mode::RX | Mode::RxF => self.wait_for_remote(req, timeout)?,
+            _ => self.mtrf.send_request_timeout(req, timeout)?,
+        };
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(RequestError::Cancelled);
+        }
+        Ok(BoundDevice {
+            mode: self.mode,
+            ch: self.ch,
+            id: match self.mode {
+                Mode::TxF | Mode::RxF => Some(resp.id),
+                _ => None,
+            },
+            request: req,
+            response: resp,
+        })
+    }
+
+    /// Stops a running session and turns bind mode off.
+    pub fn cancel(&self) -> Result<(), RequestError> {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.bind_mode_off()
+    }
+
+    fn retry(&self, req: Request, timeout: Duration) -> Result<Response, RequestError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.mtrf.send_request_timeout(req, left) {
+                Err(RequestError::DeviceNoResponse(_)) => {}
+                res => return res,
+            }
+            if self.cancelled.load(Ordering::SeqCst) {
+                return Err(RequestError::Cancelled);
+            }
+            if Instant::now() + RETRY_DELAY >= deadline {
+                return Err(RequestError::Timeout);
+            }
+            thread::sleep(RETRY_DELAY);
+        }
+    }
+
+    /// Opens bind mode and waits for the `BindSuccess` of a remote. Other
+    /// messages on the channel, e.g. presses of the remotes already bound to
+    /// it, are skipped. Bind mode is turned off on a cancel or a timeout.
+    fn wait_for_remote(&self, req: Request, timeout: Duration) -> Result<Response, RequestError> {
+        let deadline = Instant::now() + timeout;
+        // Only the first message answers the request, the later ones are
+        // unsolicited.
+        let later = self
+            .mtrf
+            .subscribe(Filter::any().mode(self.mode).ch(self.ch));
+        let mut next = self.mtrf.send_request_timeout(req, timeout);
+        loop {
+            match next {
+                Ok(resp) if resp.ctr == CtrResponse::BindSuccess => return Ok(resp),
+                Ok(_) | Err(RequestError::Timeout) => {}
+                Err(err) => return Err(err),
+            }
+            if self.cancelled.load(Ordering::SeqCst) {
+                self.bind_mode_off()?;
+                return Err(RequestError::Cancelled);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                self.bind_mode_off()?;
+                return Err(RequestError::Timeout);
+            }
+            next = match later.recv_timeout(left.min(RETRY_DELAY)) {
+                Ok(resp) => Ok(resp),
+                Err(RecvTimeoutError::Timeout) => Err(RequestError::Timeout),
+                Err(RecvTimeoutError::Disconnected) => Err(RequestError::TransportClosed),
+            };
+        }
+    }
+
+    fn bind_mode_off(&self) -> Result<(), RequestError> {
+        self.mtrf.send(Request {
+            mode: self.mode,
+            ctr: CtrRequest::BindModeOff,
+            ch: self.ch,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use crate::bind::BindSession;
+    use crate::cmd::{Cmd, Mode};
+    use crate::emulator::{Emulator, SimDevice};
+    use crate::error::RequestError;
+    use crate::mtrf::tests::Logger;
+    use crate::mtrf::Mtrf;
+    use crate::registry::{DeviceKind, Registry};
+
+    #[test]
+    pub fn bind_remote_and_device() {
+        let emulator = Emulator::new();
+        let mtrf = Mtrf::with_transport(emulator.transport(), Logger).unwrap();
+        let mut registry = Registry::new();
+
+        let session = BindSession::on_free_channel(mtrf.clone(), Mode::RxF, &registry).unwrap();
+        let remote = emulator.clone();
+        let presser = thread::spawn(move || {
+            while remote.bind_mode().is_none() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            remote.press(Mode::RxF, 0x77, Cmd::Bind);
+        });
+        let bound = session.run(Duration::from_secs(1)).unwrap();
+        presser.join().unwrap();
+        assert_eq!((bound.ch, bound.id), (0, Some(0x77)));
+        registry
+            .record_bind(
+                &bound.request,
+                &bound.response,
+                "hall.remote",
+                DeviceKind::Remote,
+            )
+            .unwrap();
+        assert_eq!(registry.free_channel(Mode::RxF), Some(1));
+
+        // The power unit enters service mode only after the session started.
+        let mut dev = SimDevice::new(0x99);
+        dev.service = false;
+        emulator.add_device(dev);
+        let switch = emulator.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            switch.update_device(0x99, |dev| dev.service = true);
+        });
+        let session = BindSession::new(mtrf, Mode::TxF, 3).unwrap();
+        let bound = session.run(Duration::from_secs(2)).unwrap();
+        assert_eq!(bound.id, Some(0x99));
+        assert_eq!(emulator.bound(Mode::TxF, 3), vec![0x99]);
+    }
+
+    /// Presses `cmd` on each remote in turn once the adapter is in bind mode.
+    fn press_in_bind_mode(emulator: &Emulator, presses: Vec<(u32, Cmd)>) -> JoinHandle<()> {
+        let remote = emulator.clone();
+        thread::spawn(move || {
+            while remote.bind_mode().is_none() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            for (id, cmd) in presses {
+                assert!(remote.press(Mode::RxF, id, cmd));
+            }
+        })
+    }
+
+    #[test]
+    pub fn skip_bound_remotes() {
+        let emulator = Emulator::new();
+        let mtrf = Mtrf::with_transport(emulator.transport(), Logger).unwrap();
+
+        let session = BindSession::new(mtrf.clone(), Mode::RxF, 5).unwrap();
+        let presser = press_in_bind_mode(&emulator, vec![(0x01, Cmd::Bind)]);
+        assert_eq!(session.run(Duration::from_secs(1)).unwrap().id, Some(0x01));
+        presser.join().unwrap();
+
+        // The remote already on the channel is pressed before the new one binds.
+        let session = BindSession::new(mtrf, Mode::RxF, 5).unwrap();
+        let presser = press_in_bind_mode(&emulator, vec![(0x01, Cmd::On), (0x02, Cmd::Bind)]);
+        assert_eq!(session.run(Duration::from_secs(1)).unwrap().id, Some(0x02));
+        presser.join().unwrap();
+        assert_eq!(emulator.bound(Mode::RxF, 5), vec![0x01, 0x02]);
+    }
+
+    #[test]
+    pub fn cancel_session() {
+        let emulator = Emulator::new();
+        let mtrf = Mtrf::with_transport(emulator.transport(), Logger).unwrap();
+        let session = BindSession::new(mtrf, Mode::RX, 5).unwrap();
+
+        let canceller = session.clone();
+        let watcher = emulator.clone();
+        thread::spawn(move || {
+            while watcher.bind_mode().is_none() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            canceller.cancel().unwrap();
+        });
+        match session.run(Duration::from_secs(2)) {
+            Err(RequestError::Cancelled) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(emulator.bind_mode(), None);
+    }
+}
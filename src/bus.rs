@@ -0,0 +1,323 @@
+//! Fan-out of the messages the adapter sends on its own to any number of
+//! subscribers.
+//!
+//! Every subscriber has a bounded queue and [`EventBus::publish`] never waits:
+//! a subscriber that falls behind loses messages instead of stalling the
+//! read loop, see [`Subscription::dropped`]. Dropping a [`Subscription`] or a
+//! [`Listener`] unsubscribes it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{
+    sync_channel, Iter, Receiver, RecvError, RecvTimeoutError, SyncSender, TryRecvError,
+    TrySendError,
+};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::cmd::response::Response;
+use crate::cmd::{Cmd, Mode};
+use crate::mtrf::OnMessage;
+
+/// Messages queued for a subscriber before new ones are dropped.
+pub const CAPACITY: usize = 256;
+
+/// Which messages a subscriber gets. Unset fields match anything.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    mode: Option<Mode>,
+    ch: Option<u8>,
+    cmd: Option<u8>,
+    id: Option<u32>,
+}
+
+impl Filter {
+    /// Matches every message.
+    pub fn any() -> Filter {
+        Filter::default()
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Filter {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn ch(mut self, ch: u8) -> Filter {
+        self.ch = Some(ch);
+        self
+    }
+
+    /// Matches the kind of the command, the payload is ignored.
+    pub fn cmd(mut self, cmd: Cmd) -> Filter {
+        self.cmd = Some(cmd.as_u8());
+        self
+    }
+
+    /// Matches the address of a nooLite-F device.
+    pub fn id(mut self, id: u32) -> Filter {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn matches(&self, resp: &Response) -> bool {
+        self.mode.map(|mode| mode == resp.mode).unwrap_or(true)
+            && self.ch.map(|ch| ch == resp.ch).unwrap_or(true)
+            && self.cmd.map(|cmd| cmd == resp.cmd.as_u8()).unwrap_or(true)
+            && self.id.map(|id| id == resp.id).unwrap_or(true)
+    }
+}
+
+struct Subscriber {
+    id: u64,
+    filter: Filter,
+    queue: SyncSender<Response>,
+    dropped: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    list: Vec<Subscriber>,
+    next_id: u64,
+    closed: bool,
+}
+
+/// Publish side of the subscriptions. Clones share the subscribers.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    /// Queues the messages matching `filter`. Once the bus is closed the
+    /// subscription gets nothing and `recv` fails.
+    pub fn subscribe(&self, filter: Filter) -> Subscription {
+        let (queue, rx) = sync_channel(CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        if !subscribers.closed {
+            subscribers.list.push(Subscriber {
+                id,
+                filter,
+                queue,
+                dropped: dropped.clone(),
+            });
+        }
+        Subscription {
+            rx,
+            token: Token {
+                id,
+                subscribers: Arc::downgrade(&self.subscribers),
+                dropped,
+            },
+        }
+    }
+
+    /// Calls `handler` with the messages matching `filter` on a thread of its
+    /// own, until the listener is dropped or the bus is closed.
+    pub fn subscribe_with<F>(&self, filter: Filter, mut handler: F) -> Listener
+    where
+        F: FnMut(Response) + Send + 'static,
+    {
+        let Subscription { rx, token } = self.subscribe(filter);
+        thread::spawn(move || {
+            for resp in rx {
+                handler(resp);
+            }
+        });
+        Listener { token }
+    }
+
+    /// Hands the message to every matching subscriber without waiting.
+    pub fn publish(&self, resp: &Response) {
+        self.subscribers.lock().unwrap().list.retain(|sub| {
+            if !sub.filter.matches(resp) {
+                return true;
+            }
+            match sub.queue.try_send(*resp) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    sub.dropped.fetch_add(1, Ordering::Relaxed);
+                    debug!("Subscriber {} lags behind, dropped {}", sub.id, resp);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
+    /// Ends every subscription, now and later ones.
+    pub fn close(&self) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.closed = true;
+        subscribers.list.clear();
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().list.len()
+    }
+}
+
+impl OnMessage for EventBus {
+    fn on_message(&mut self, msg: Response) {
+        self.publish(&msg);
+    }
+}
+
+/// Removes the subscriber when dropped.
+struct Token {
+    id: u64,
+    subscribers: Weak<Mutex<Subscribers>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            subscribers
+                .lock()
+                .unwrap()
+                .list
+                .retain(|sub| sub.id != self.id);
+        }
+    }
+}
+
+/// Queue of the messages of one subscriber.
+pub struct Subscription {
+    rx: Receiver<Response>,
+    token: Token,
+}
+
+impl Subscription {
+    /// Waits for a message, fails once the bus is closed.
+    pub fn recv(&self) -> Result<Response, RecvError> {
+        self.rx.recv()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Response, RecvTimeoutError> {
+        self.rx.recv_timeout(timeout)
+    }
+
+    pub fn try_recv(&self) -> Result<Response, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    /// Messages until the bus is closed.
+    pub fn iter(&self) -> Iter<'_, Response> {
+        self.rx.iter()
+    }
+
+    /// Messages lost because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.token.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Handle of a [`EventBus::subscribe_with`] callback.
+pub struct Listener {
+    token: Token,
+}
+
+impl Listener {
+    /// Messages lost because the callback was too slow.
+    pub fn dropped(&self) -> u64 {
+        self.token.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::time::Duration;
+
+    use crate::bus::{EventBus, Filter, CAPACITY};
+    use crate::cmd::response::Response;
+    use crate::cmd::{Cmd, CtrResponse, Mode};
+
+    fn message(mode: Mode, ch: u8, cmd: Cmd, id: u32) -> Response {
+        Response {
+            mode,
+            ctr: CtrResponse::Success,
+            togl: 0,
+            ch,
+            cmd,
+            fmt: 0,
+            data: [0; 4],
+            id,
+            crc: 0,
+        }
+    }
+
+    #[test]
+    pub fn filters() {
+        let switch = message(Mode::RxF, 3, Cmd::Switch, 0xAB);
+        assert!(Filter::any().matches(&switch));
+        assert!(Filter::any()
+            .mode(Mode::RxF)
+            .ch(3)
+            .cmd(Cmd::Switch)
+            .id(0xAB)
+            .matches(&switch));
+        assert!(!Filter::any().mode(Mode::RX).matches(&switch));
+        assert!(!Filter::any().ch(4).matches(&switch));
+        assert!(!Filter::any().cmd(Cmd::On).matches(&switch));
+        assert!(!Filter::any().id(0xAC).matches(&switch));
+
+        let temperature = message(Mode::RX, 1, Cmd::SensTempHumi, 0);
+        let bus = EventBus::new();
+        let all = bus.subscribe(Filter::any());
+        let sensors = bus.subscribe(Filter::any().cmd(Cmd::SensTempHumi));
+        bus.publish(&switch);
+        bus.publish(&temperature);
+        assert_eq!(all.try_recv().unwrap(), switch);
+        assert_eq!(all.try_recv().unwrap(), temperature);
+        assert_eq!(sensors.try_recv().unwrap(), temperature);
+        assert!(sensors.try_recv().is_err());
+    }
+
+    #[test]
+    pub fn unsubscribe_and_close() {
+        let bus = EventBus::new();
+        let first = bus.subscribe(Filter::any());
+        let (tx, rx) = channel();
+        let listener = bus.subscribe_with(Filter::any().ch(2), move |resp| {
+            tx.send(resp).unwrap();
+        });
+        assert_eq!(bus.subscriber_count(), 2);
+
+        let msg = message(Mode::RX, 2, Cmd::On, 0);
+        bus.publish(&msg);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), msg);
+        drop(listener);
+        assert_eq!(bus.subscriber_count(), 1);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+
+        bus.close();
+        assert_eq!(first.iter().collect::<Vec<_>>(), vec![msg]);
+        assert!(bus.subscribe(Filter::any()).recv().is_err());
+        assert_eq!(bus.subscriber_count(), 0);
+    }
+
+    #[test]
+    pub fn slow_subscriber_loses_messages() {
+        let bus = EventBus::new();
+        let slow = bus.subscribe(Filter::any());
+        let msg = message(Mode::RX, 0, Cmd::Switch, 0);
+        for _ in 0..CAPACITY + 10 {
+            bus.publish(&msg);
+        }
+        assert_eq!(slow.dropped(), 10);
+        assert_eq!(slow.iter().take(CAPACITY).count(), CAPACITY);
+        bus.publish(&msg);
+        assert_eq!(slow.try_recv().unwrap(), msg);
+    }
+}
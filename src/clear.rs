@@ -0,0 +1,298 @@
+//! Clearing a channel or the whole bind memory of a mode.
+//!
+//! Both operations need a [`Confirmation`], which is only handed out for the
+//! exact phrase describing the operation, e.g.
+//! `Wipe::Memory(Mode::RX).confirm("clear memory of Rx")`. [`clear`] copies
+//! the affected registry entries into a [`Backup`] before anything is sent.
+
+use std::fmt;
+#[cfg(feature = "serde")]
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::cmd::request::Request;
+use crate::cmd::response::Response;
+use crate::cmd::{ClearKey, Cmd, CtrRequest, CtrResponse, Mode, CHANNELS};
+use crate::error::RequestError;
+use crate::mtrf::Mtrf;
+use crate::registry::{DeviceEntry, Registry};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Wipe {
+    /// Unbinds everything from one channel.
+    Channel { mode: Mode, ch: u8 },
+    /// Unbinds everything from every channel of the mode.
+    Memory(Mode),
+}
+
+impl Wipe {
+    /// Hands out the confirmation if `phrase` repeats the description of the wipe.
+    pub fn confirm(self, phrase: &str) -> Result<Confirmation, Error> {
+        let mode = match self {
+            Wipe::Channel { mode, ch } => {
+                ensure!(ch < CHANNELS, "Invalid channel:{}", ch);
+                mode
+            }
+            Wipe::Memory(mode) => mode,
+        };
+        ensure!(
+            matches!(mode, Mode::TX | Mode::RX | Mode::TxF | Mode::RxF),
+            "Mode {} has no bind memory",
+            mode
+        );
+        ensure!(phrase == self.to_string(), "Type \"{}\" to confirm", self);
+        Ok(Confirmation { wipe: self })
+    }
+
+    pub fn mode(&self) -> Mode {
+        match *self {
+            Wipe::Channel { mode, .. } | Wipe::Memory(mode) => mode,
+        }
+    }
+
+    fn request(&self) -> Request {
+        match *self {
+            Wipe::Channel { mode, ch } => Request {
+                mode,
+                ctr: CtrRequest::ClearChannel,
+                ch,
+                ..Default::default()
+            },
+            Wipe::Memory(mode) => Request {
+                mode,
+                ctr: CtrRequest::ClearMemory,
+                cmd: Cmd::ClearMemory(ClearKey(())),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn affects(&self, dev: &DeviceEntry) -> bool {
+        match *self {
+            Wipe::Channel { mode, ch } => dev.mode == mode && dev.ch == ch,
+            Wipe::Memory(mode) => dev.mode == mode,
+        }
+    }
+
+    fn is_ack(&self, resp: &Response) -> bool {
+        let ch = match *self {
+            Wipe::Channel { ch, .. } => ch,
+            Wipe::Memory(_) => 0,
+        };
+        resp.ctr == CtrResponse::Success && resp.mode == self.mode() && resp.ch == ch
+    }
+}
+
+impl fmt::Display for Wipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Wipe::Channel { mode, ch } => write!(f, "clear channel {} of {}", ch, mode),
+            Wipe::Memory(mode) => write!(f, "clear memory of {}", mode),
+        }
+    }
+}
+
+/// Permission for one wipe, consumed by [`clear`].
+#[derive(Debug)]
+pub struct Confirmation {
+    wipe: Wipe,
+}
+
+impl Confirmation {
+    pub fn wipe(&self) -> Wipe {
+        self.wipe
+    }
+}
+
+/// Registry entries removed by a wipe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    pub wipe: Wipe,
+    pub entries: Vec<DeviceEntry>,
+}
+
+impl Backup {
+    fn take(registry: &Registry, wipe: Wipe) -> Backup {
+        Backup {
+            wipe,
+            entries: registry
+                .devices()
+                .iter()
+                .filter(|dev| wipe.affects(dev))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Writes the entries in the registry file format.
+    #[cfg(feature = "serde")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut registry = Registry::new();
+        for entry in &self.entries {
+            registry.add(entry.clone())?;
+        }
+        registry.save(path)
+    }
+
+    /// Writes the entries next to the registry file, `devices.toml` is backed
+    /// up to `devices.bak.json`. Returns the path written.
+    #[cfg(feature = "serde")]
+    pub fn save_beside<P: AsRef<Path>>(&self, registry_path: P) -> Result<PathBuf, Error> {
+        let path = registry_path.as_ref().with_extension("bak.json");
+        self.save(&path)?;
+        Ok(path)
+    }
+
+    /// Puts the entries back, e.g. after binding the devices again.
+    pub fn restore(&self, registry: &mut Registry) -> Result<(), Error> {
+        for entry in &self.entries {
+            registry.add(entry.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// Performs the confirmed wipe.
+///
+/// The affected registry entries are copied into a [`Backup`] and handed to
+/// `keep`, e.g. [`Backup::save_beside`], before anything is sent. Nothing is
+/// wiped if `keep` fails. The entries are removed from the registry once the
+/// adapter acknowledged the wipe, the registry is not locked while waiting.
+pub fn clear<K>(
+    mtrf: &Mtrf,
+    registry: &Mutex<Registry>,
+    confirmation: Confirmation,
+    timeout: Duration,
+    keep: K,
+) -> Result<Backup, Error>
+where
+    K: FnOnce(&Backup) -> Result<(), Error>,
+{
+    let wipe = confirmation.wipe;
+    let backup = Backup::take(&registry.lock().unwrap(), wipe);
+    keep(&backup)?;
+
+    let req = wipe.request();
+    info!("{}", wipe);
+    let resp = mtrf.request_unguarded(req, timeout)?;
+    if !wipe.is_ack(&resp) {
+        return Err(RequestError::UnexpectedResponse(resp).into());
+    }
+    registry.lock().unwrap().record_unbind(&req, &resp);
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use crate::clear::{clear, Wipe};
+    use crate::cmd::request::{bind, Request};
+    use crate::cmd::{ClearKey, Cmd, CtrRequest, Mode, CLEAR_MEMORY_KEY};
+    use crate::emulator::{Emulator, SimDevice};
+    use crate::error::RequestError;
+    use crate::mtrf::tests::Logger;
+    use crate::mtrf::Mtrf;
+    use crate::registry::{DeviceKind, Registry};
+
+    #[test]
+    pub fn unconfirmed_wipes() {
+        let emulator = Emulator::new();
+        let mtrf = Mtrf::with_transport(emulator.transport(), Logger).unwrap();
+        let raw = Request {
+            mode: Mode::TxF,
+            ctr: CtrRequest::ClearChannel,
+            ..Default::default()
+        };
+        match mtrf.send_request(raw) {
+            Err(RequestError::InvalidRequest(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        // The key decoded from raw bytes is no way around the confirmation.
+        let forged = Request {
+            mode: Mode::TxF,
+            cmd: Cmd::ClearMemory(ClearKey(())),
+            ..Default::default()
+        }
+        .to_message();
+        let forged = Request::try_from(forged).unwrap();
+        match mtrf.send_request(forged) {
+            Err(RequestError::InvalidRequest(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let forged = Request {
+            mode: Mode::TxF,
+            cmd: Cmd::Unknown {
+                cmd: 132,
+                fmt: 4,
+                data: CLEAR_MEMORY_KEY,
+            },
+            ..Default::default()
+        };
+        match mtrf.send_request(forged) {
+            Err(RequestError::InvalidRequest(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    pub fn confirmed_wipes() {
+        let emulator = Emulator::new();
+        emulator.add_device(SimDevice::new(1));
+        emulator.add_device(SimDevice::new(2));
+        let mtrf = Mtrf::with_transport(emulator.transport(), Logger).unwrap();
+        let mut registry = Registry::new();
+        for (ch, name) in [(0, "hall.lamp"), (1, "hall.fan")].iter() {
+            let req = bind(Mode::TxF, *ch);
+            let resp = mtrf.send_request(req).unwrap();
+            registry
+                .record_bind(&req, &resp, name, DeviceKind::Relay)
+                .unwrap();
+        }
+
+        let registry = Mutex::new(registry);
+        let timeout = Duration::from_secs(1);
+
+        let wipe = Wipe::Channel {
+            mode: Mode::TxF,
+            ch: 0,
+        };
+        assert!(wipe.confirm("yes").is_err());
+        let confirmation = wipe.confirm("clear channel 0 of TxF").unwrap();
+        let failed = clear(&mtrf, &registry, confirmation, timeout, |_| {
+            bail!("Disk full")
+        });
+        assert!(failed.is_err());
+        assert_eq!(emulator.bound(Mode::TxF, 0), vec![1]);
+
+        let confirmation = wipe.confirm("clear channel 0 of TxF").unwrap();
+        let backup = clear(&mtrf, &registry, confirmation, timeout, |backup| {
+            // Kept before the adapter forgets anything.
+            assert_eq!(backup.entries.len(), 1);
+            assert_eq!(emulator.bound(Mode::TxF, 0), vec![1]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(backup.entries[0].full_name(), "hall.lamp");
+        assert_eq!(registry.lock().unwrap().devices().len(), 1);
+        assert!(emulator.bound(Mode::TxF, 0).is_empty());
+        assert_eq!(emulator.bound(Mode::TxF, 1), vec![2]);
+
+        let confirmation = Wipe::Memory(Mode::TxF)
+            .confirm("clear memory of TxF")
+            .unwrap();
+        let backup = clear(&mtrf, &registry, confirmation, timeout, |_| Ok(())).unwrap();
+        assert_eq!(backup.entries[0].full_name(), "hall.fan");
+        assert!(emulator.bound(Mode::TxF, 1).is_empty());
+        let mut registry = registry.into_inner().unwrap();
+        assert!(registry.devices().is_empty());
+
+        backup.restore(&mut registry).unwrap();
+        assert!(registry.device("hall.fan").is_some());
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use anyhow::Error;
+
+use crate::cmd::request::Request;
+use crate::cmd::{Cmd, CtrRequest, Mode, CHANNELS};
+
+/// Recipient of a command. Decides the `ctr`, `ch` and `id` of a request.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// Devices bound to the channel.
+    Channel(u8),
+    /// Every device bound to the channel, without waiting for answers.
+    Broadcast(u8),
+    /// A nooLite-F device, wherever it is bound.
+    Id(u32),
+    /// A nooLite-F device, only if it is bound to the channel.
+    IdInChannel(u8, u32),
+}
+
+impl Address {
+    /// Address a request is sent to, as told by its `ctr`.
+    pub fn of(req: &Request) -> Address {
+        match req.ctr {
+            CtrRequest::SendBroadcastCommand => Address::Broadcast(req.ch),
+            CtrRequest::SendCommandToId => Address::Id(req.id),
+            CtrRequest::SendCommandToIdInChannel => Address::IdInChannel(req.ch, req.id),
+            _ => Address::Channel(req.ch),
+        }
+    }
+
+    pub fn ch(&self) -> Option<u8> {
+        match *self {
+            Address::Channel(ch) | Address::Broadcast(ch) | Address::IdInChannel(ch, _) => Some(ch),
+            Address::Id(_) => None,
+        }
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        match *self {
+            Address::Id(id) | Address::IdInChannel(_, id) => Some(id),
+            Address::Channel(_) | Address::Broadcast(_) => None,
+        }
+    }
+
+    /// Checks that the address can be used in `mode`.
+    pub fn validate(&self, mode: Mode) -> Result<(), Error> {
+        if let Some(ch) = self.ch() {
+            ensure!(ch < CHANNELS, "The ch value must be between 0 and 63");
+        }
+        match self {
+            Address::Channel(_) => {}
+            Address::Broadcast(_) => ensure!(
+                mode == Mode::TX || mode == Mode::TxF,
+                "Broadcast is only sent in TX and TX-F modes, not {}",
+                mode
+            ),
+            Address::Id(_) | Address::IdInChannel(..) => ensure!(
+                mode == Mode::TxF,
+                "Addressing by ID needs TX-F mode, not {}",
+                mode
+            ),
+        }
+        Ok(())
+    }
+
+    /// Builds a validated request of `cmd` to this address.
+    pub fn request(&self, mode: Mode, cmd: Cmd) -> Result<Request, Error> {
+        self.validate(mode)?;
+        let ctr = match self {
+            Address::Channel(_) => CtrRequest::SendCommand,
+            Address::Broadcast(_) => CtrRequest::SendBroadcastCommand,
+            Address::Id(_) => CtrRequest::SendCommandToId,
+            Address::IdInChannel(..) => CtrRequest::SendCommandToIdInChannel,
+        };
+        Ok(Request {
+            mode,
+            ctr,
+            ch: self.ch().unwrap_or(0),
+            cmd,
+            id: self.id().unwrap_or(0),
+        })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Channel(ch) => write!(f, "ch:{}", ch),
+            Address::Broadcast(ch) => write!(f, "broadcast:{}", ch),
+            Address::Id(id) => write!(f, "id:{:#010x}", id),
+            Address::IdInChannel(ch, id) => write!(f, "ch:{}/id:{:#010x}", ch, id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::address::Address;
+    use crate::cmd::{Cmd, CtrRequest, Mode};
+
+    #[test]
+    pub fn drive_request_fields() {
+        let addresses = [
+            (Address::Channel(3), CtrRequest::SendCommand),
+            (Address::Broadcast(3), CtrRequest::SendBroadcastCommand),
+            (Address::Id(0xBEEF), CtrRequest::SendCommandToId),
+            (
+                Address::IdInChannel(3, 0xBEEF),
+                CtrRequest::SendCommandToIdInChannel,
+            ),
+        ];
+        for (address, ctr) in addresses.iter() {
+            let req = address.request(Mode::TxF, Cmd::On).unwrap();
+            assert_eq!(req.ctr, *ctr);
+            assert_eq!(Address::of(&req), *address);
+        }
+    }
+
+    #[test]
+    pub fn reject_invalid() {
+        assert!(Address::Channel(64).request(Mode::TX, Cmd::On).is_err());
+        assert!(Address::IdInChannel(64, 1)
+            .request(Mode::TxF, Cmd::On)
+            .is_err());
+        assert!(Address::Id(1).request(Mode::TX, Cmd::On).is_err());
+        assert!(Address::Broadcast(1).request(Mode::RX, Cmd::On).is_err());
+        assert!(Address::Channel(63).request(Mode::RX, Cmd::Bind).is_ok());
+    }
+}
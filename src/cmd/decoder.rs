@@ -0,0 +1,148 @@
+use crate::cmd::{crc, CRC_INDEX, MESSAGE_LENGTH, RESPONSE_SP, RESPONSE_ST};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecoderStats {
+    /// Frames that passed the start, stop and crc checks.
+    pub frames: u64,
+    /// Times the stream lost alignment and had to be rescanned for a start byte.
+    pub resyncs: u64,
+    /// Bytes discarded while realigning.
+    pub dropped_bytes: u64,
+}
+
+/// Splits a raw byte stream into 17-byte frames.
+///
+/// Bytes that do not belong to a valid frame are skipped until the next start
+/// byte, so a lost or spurious byte only costs the frame it landed in.
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    start: u8,
+    stop: u8,
+    in_sync: bool,
+    stats: DecoderStats,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        FrameDecoder::new()
+    }
+}
+
+impl FrameDecoder {
+    /// Decoder for frames sent by the adapter.
+    pub fn new() -> FrameDecoder {
+        FrameDecoder::with_markers(RESPONSE_ST, RESPONSE_SP)
+    }
+
+    pub fn with_markers(start: u8, stop: u8) -> FrameDecoder {
+        FrameDecoder {
+            buf: Vec::with_capacity(MESSAGE_LENGTH * 2),
+            start,
+            stop,
+            in_sync: true,
+            stats: DecoderStats::default(),
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn next_frame(&mut self) -> Option<[u8; MESSAGE_LENGTH]> {
+        loop {
+            let skip = self
+                .buf
+                .iter()
+                .position(|b| *b == self.start)
+                .unwrap_or(self.buf.len());
+            self.discard(skip);
+
+            if self.buf.len() < MESSAGE_LENGTH {
+                return None;
+            }
+
+            let mut frame = [0; MESSAGE_LENGTH];
+            frame.copy_from_slice(&self.buf[..MESSAGE_LENGTH]);
+            if frame[MESSAGE_LENGTH - 1] == self.stop && crc(&frame) == frame[CRC_INDEX] {
+                self.buf.drain(..MESSAGE_LENGTH);
+                self.in_sync = true;
+                self.stats.frames += 1;
+                return Some(frame);
+            }
+
+            // The start byte was payload, look for the next one.
+            self.discard(1);
+        }
+    }
+
+    /// Forgets buffered bytes without counting them as dropped, e.g. after a reconnect.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.in_sync = true;
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    /// Bytes received but not yet assembled into a frame.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn discard(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        if self.in_sync {
+            self.in_sync = false;
+            self.stats.resyncs += 1;
+        }
+        self.stats.dropped_bytes += count as u64;
+        self.buf.drain(..count);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::decoder::{DecoderStats, FrameDecoder};
+    use crate::cmd::{crc, CRC_INDEX, MESSAGE_LENGTH, RESPONSE_SP, RESPONSE_ST};
+
+    fn frame(ch: u8) -> [u8; MESSAGE_LENGTH] {
+        let mut msg = [0; MESSAGE_LENGTH];
+        msg[0] = RESPONSE_ST;
+        msg[1] = 3;
+        msg[4] = ch;
+        msg[5] = RESPONSE_ST;
+        msg[CRC_INDEX] = crc(&msg);
+        msg[16] = RESPONSE_SP;
+        msg
+    }
+
+    #[test]
+    pub fn realign_after_junk() {
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&[1, 2, RESPONSE_ST]);
+        decoder.push(&frame(1));
+        // Lose the first byte of the next frame.
+        decoder.push(&frame(2)[1..]);
+        decoder.push(&frame(3)[..10]);
+
+        assert_eq!(decoder.next_frame(), Some(frame(1)));
+        assert_eq!(decoder.next_frame(), None);
+        decoder.push(&frame(3)[10..]);
+        assert_eq!(decoder.next_frame(), Some(frame(3)));
+        assert_eq!(decoder.next_frame(), None);
+        assert_eq!(decoder.pending(), 0);
+
+        let stats = decoder.stats();
+        assert_eq!(
+            stats,
+            DecoderStats {
+                frames: 2,
+                resyncs: 2,
+                dropped_bytes: 3 + 16,
+            }
+        );
+    }
+}
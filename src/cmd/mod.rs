@@ -1,13 +1,26 @@
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 
 use anyhow::Error;
 
+pub mod address;
+pub mod decoder;
+pub mod payload;
+pub mod repr;
 pub mod request;
 pub mod response;
+pub mod sensor;
+pub mod state;
+
+/// Channels per mode in the adapter memory.
+pub const CHANNELS: u8 = 64;
 
 pub const CH_INDEX: usize = 4;
 pub const CMD_INDEX: usize = 5;
+pub const FMT_INDEX: usize = 6;
+pub const DATA_INDEX: usize = 7;
+pub const ID_INDEX: usize = 11;
 
 pub const CRC_INDEX: usize = 15;
 
@@ -15,9 +28,21 @@ pub const MESSAGE_LENGTH: usize = 17;
 
 pub const REQUEST_ST: u8 = 171;
 pub const RESPONSE_ST: u8 = 173;
+pub const REQUEST_SP: u8 = 172;
+pub const RESPONSE_SP: u8 = 174;
+
+/// D0–D3 of a `ClearMemory` request, guarding against an accidental wipe.
+pub const CLEAR_MEMORY_KEY: [u8; 4] = [170, 85, 170, 85];
+
+/// Checksum of a frame: the low byte of the sum of the first 15 bytes.
+pub fn crc(msg: &[u8; MESSAGE_LENGTH]) -> u8 {
+    let sum: u32 = msg.iter().take(CRC_INDEX).map(|b| *b as u32).sum();
+    sum.to_le_bytes()[0]
+}
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
 pub enum Mode {
+    #[default]
     TX = 0,
     RX = 1,
     TxF = 2,
@@ -45,24 +70,53 @@ impl TryFrom<u8> for Mode {
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         Ok(match value {
             0 => Mode::TX,
-            1 => Mode::TX,
-            2 => Mode::TX,
-            3 => Mode::TX,
-            4 => Mode::TX,
-            5 => Mode::TX,
+            1 => Mode::RX,
+            2 => Mode::TxF,
+            3 => Mode::RxF,
+            4 => Mode::Service,
+            5 => Mode::FirmwareUpdate,
             _ => return Err(anyhow!("Failed to decode mode:{}", value)),
         })
     }
 }
 
-impl Default for Mode {
-    fn default() -> Self {
-        Mode::TX
+impl FromStr for Mode {
+    type Err = Error;
+
+    /// Parses the names printed by `Display` and returned by `name`, ignoring
+    /// case, dashes and underscores.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(
+            match s.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+                "tx" => Mode::TX,
+                "rx" => Mode::RX,
+                "txf" => Mode::TxF,
+                "rxf" => Mode::RxF,
+                "service" => Mode::Service,
+                "firmwareupdate" => Mode::FirmwareUpdate,
+                _ => return Err(anyhow!("Unknown mode:{}", s)),
+            },
+        )
+    }
+}
+
+impl Mode {
+    /// Snake-case name, e.g. `txf`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::TX => "tx",
+            Mode::RX => "rx",
+            Mode::TxF => "txf",
+            Mode::RxF => "rxf",
+            Mode::Service => "service",
+            Mode::FirmwareUpdate => "firmware_update",
+        }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub enum CtrRequest {
+    #[default]
     SendCommand = 0,
     SendBroadcastCommand = 1,
     ReadResponse = 2,
@@ -92,13 +146,57 @@ impl fmt::Display for CtrRequest {
     }
 }
 
-impl Default for CtrRequest {
-    fn default() -> Self {
-        CtrRequest::SendCommand
+impl TryFrom<u8> for CtrRequest {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        Ok(match value {
+            0 => CtrRequest::SendCommand,
+            1 => CtrRequest::SendBroadcastCommand,
+            2 => CtrRequest::ReadResponse,
+            3 => CtrRequest::BindModeOn,
+            4 => CtrRequest::BindModeOff,
+            5 => CtrRequest::ClearChannel,
+            6 => CtrRequest::ClearMemory,
+            7 => CtrRequest::UnbindAddressFromChannel,
+            8 => CtrRequest::SendCommandToIdInChannel,
+            9 => CtrRequest::SendCommandToId,
+            _ => return Err(anyhow!("Failed to decode ctr request:{}", value)),
+        })
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+impl FromStr for CtrRequest {
+    type Err = Error;
+
+    /// Parses the names returned by `name`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..=9)
+            .filter_map(|ctr| CtrRequest::try_from(ctr).ok())
+            .find(|ctr| ctr.name() == s)
+            .ok_or_else(|| anyhow!("Unknown ctr request:{}", s))
+    }
+}
+
+impl CtrRequest {
+    /// Snake-case name, e.g. `send_command`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CtrRequest::SendCommand => "send_command",
+            CtrRequest::SendBroadcastCommand => "send_broadcast_command",
+            CtrRequest::ReadResponse => "read_response",
+            CtrRequest::BindModeOn => "bind_mode_on",
+            CtrRequest::BindModeOff => "bind_mode_off",
+            CtrRequest::ClearChannel => "clear_channel",
+            CtrRequest::ClearMemory => "clear_memory",
+            CtrRequest::UnbindAddressFromChannel => "unbind_address_from_channel",
+            CtrRequest::SendCommandToIdInChannel => "send_command_to_id_in_channel",
+            CtrRequest::SendCommandToId => "send_command_to_id",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum CtrResponse {
     Success = 0,
     NoResponse = 1,
@@ -131,8 +229,33 @@ impl TryFrom<u8> for CtrResponse {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+impl FromStr for CtrResponse {
+    type Err = Error;
+
+    /// Parses the names returned by `name`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..=3)
+            .filter_map(|ctr| CtrResponse::try_from(ctr).ok())
+            .find(|ctr| ctr.name() == s)
+            .ok_or_else(|| anyhow!("Unknown ctr response:{}", s))
+    }
+}
+
+impl CtrResponse {
+    /// Snake-case name, e.g. `bind_success`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CtrResponse::Success => "success",
+            CtrResponse::NoResponse => "no_response",
+            CtrResponse::Error => "error",
+            CtrResponse::BindSuccess => "bind_success",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub enum Cmd {
+    #[default]
     Off,
     BrightDown,
     On,
@@ -160,9 +283,21 @@ pub enum Cmd {
     WriteState,
     SendState,
     Service(bool),
-    ClearMemory,
+    /// Only built by [`crate::clear`], so a plain `Request` cannot wipe the memory.
+    ClearMemory(ClearKey),
+    /// A command byte or FMT this crate does not decode, e.g. from newer
+    /// firmware, kept as received.
+    Unknown {
+        cmd: u8,
+        fmt: u8,
+        data: [u8; 4],
+    },
 }
 
+/// Token carrying the `ClearMemory` key. It cannot be built outside the crate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ClearKey(pub(crate) ());
+
 impl fmt::Display for Cmd {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -193,7 +328,10 @@ impl fmt::Display for Cmd {
             Cmd::WriteState => write!(f, "WriteState"),
             Cmd::SendState => write!(f, "SendState"),
             Cmd::Service(bl) => write!(f, "Service({})", if *bl { 1 } else { 0 }),
-            Cmd::ClearMemory => write!(f, "ClearMemory"),
+            Cmd::ClearMemory(_) => write!(f, "ClearMemory"),
+            Cmd::Unknown { cmd, fmt, data } => {
+                write!(f, "Unknown({} fmt={} D={:?})", cmd, fmt, data)
+            }
         }
     }
 }
@@ -201,7 +339,15 @@ impl fmt::Display for Cmd {
 impl TryFrom<&[u8]> for Cmd {
     type Error = Error;
 
+    /// Decodes `cmd, fmt, D0-D3`. Commands it does not know are kept as
+    /// [`Cmd::Unknown`], only a short slice is an error.
     fn try_from(value: &[u8]) -> Result<Self, Error> {
+        ensure!(value.len() >= 6, "Failed to decode cmd: {:?}", value);
+        let unknown = || Cmd::Unknown {
+            cmd: value[0],
+            fmt: value[1],
+            data: [value[2], value[3], value[4], value[5]],
+        };
         Ok(match value[0] {
             0 => Cmd::Off,
             1 => Cmd::BrightDown,
@@ -209,7 +355,9 @@ impl TryFrom<&[u8]> for Cmd {
             3 => Cmd::BrightUp,
             4 => Cmd::Switch,
             5 => Cmd::BrightBack,
-            6 => Cmd::SetBrightness(SetBrightness::try_from(&value[1..])?),
+            6 => {
+                SetBrightness::try_from(&value[1..]).map_or_else(|_| unknown(), Cmd::SetBrightness)
+            }
             7 => Cmd::LoadPreset,
             8 => Cmd::SavePreset,
             9 => Cmd::Unbind,
@@ -224,25 +372,19 @@ impl TryFrom<&[u8]> for Cmd {
             19 => Cmd::SpeedMode,
             20 => Cmd::BatteryLow,
             21 => Cmd::SensTempHumi,
-            25 => Cmd::TemporaryOn(TemporaryOn::try_from(&value[1..])?),
+            25 => TemporaryOn::try_from(&value[1..]).map_or_else(|_| unknown(), Cmd::TemporaryOn),
             26 => Cmd::Modes,
             128 => Cmd::ReadState,
             129 => Cmd::WriteState,
             130 => Cmd::SendState,
             131 => Cmd::Service(value[2] == 1),
-            132 => Cmd::ClearMemory,
-            _ => return Err(anyhow!("Failed to decode cmd: {:?}", value)),
+            132 if value[2..6] == CLEAR_MEMORY_KEY => Cmd::ClearMemory(ClearKey(())),
+            _ => unknown(),
         })
     }
 }
 
-impl Default for Cmd {
-    fn default() -> Self {
-        Cmd::Off
-    }
-}
-
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SetBrightness {
     Fmt1(u8),
     Fmt3([u8; 3]),
@@ -283,7 +425,7 @@ impl fmt::Display for SetBrightness {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum TemporaryOn {
     Fmt1(u8),
     Fmt2([u8; 2]),
@@ -295,7 +437,7 @@ impl TryFrom<&[u8]> for TemporaryOn {
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         Ok(match value[0] {
             1 => TemporaryOn::Fmt1(value[1]),
-            3 => {
+            2 => {
                 let mut buf = [0; 2];
                 buf[0] = value[1];
                 buf[1] = value[2];
@@ -323,6 +465,41 @@ impl fmt::Display for TemporaryOn {
 }
 
 impl Cmd {
+    /// Snake-case name without the payload, e.g. `sens_temp_humi`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cmd::Off => "off",
+            Cmd::BrightDown => "bright_down",
+            Cmd::On => "on",
+            Cmd::BrightUp => "bright_up",
+            Cmd::Switch => "switch",
+            Cmd::BrightBack => "bright_back",
+            Cmd::SetBrightness(_) => "set_brightness",
+            Cmd::LoadPreset => "load_preset",
+            Cmd::SavePreset => "save_preset",
+            Cmd::Unbind => "unbind",
+            Cmd::StopBright => "stop_bright",
+            Cmd::BrightStepDown => "bright_step_down",
+            Cmd::BrightStepUp => "bright_step_up",
+            Cmd::BrightReg(_) => "bright_reg",
+            Cmd::Bind => "bind",
+            Cmd::RollColor => "roll_color",
+            Cmd::SwitchColor => "switch_color",
+            Cmd::SwitchMode => "switch_mode",
+            Cmd::SpeedMode => "speed_mode",
+            Cmd::BatteryLow => "battery_low",
+            Cmd::SensTempHumi => "sens_temp_humi",
+            Cmd::TemporaryOn(_) => "temporary_on",
+            Cmd::Modes => "modes",
+            Cmd::ReadState => "read_state",
+            Cmd::WriteState => "write_state",
+            Cmd::SendState => "send_state",
+            Cmd::Service(_) => "service",
+            Cmd::ClearMemory(_) => "clear_memory",
+            Cmd::Unknown { .. } => "unknown",
+        }
+    }
+
     pub fn as_u8(&self) -> u8 {
         match self {
             Cmd::Off => 0,
@@ -352,7 +529,123 @@ impl Cmd {
             Cmd::WriteState => 129,
             Cmd::SendState => 130,
             Cmd::Service(_) => 131,
-            Cmd::ClearMemory => 132,
+            Cmd::ClearMemory(_) => 132,
+            Cmd::Unknown { cmd, .. } => *cmd,
+        }
+    }
+
+    /// FMT and D0–D3 bytes the command is sent with.
+    pub fn payload(&self) -> (u8, [u8; 4]) {
+        match *self {
+            Cmd::SetBrightness(SetBrightness::Fmt1(d0)) => (1, [d0, 0, 0, 0]),
+            Cmd::SetBrightness(SetBrightness::Fmt3(d)) => (3, [d[0], d[1], d[2], 0]),
+            Cmd::BrightReg(reg) => (1, [reg, 0, 0, 0]),
+            Cmd::TemporaryOn(TemporaryOn::Fmt1(d0)) => (1, [d0, 0, 0, 0]),
+            Cmd::TemporaryOn(TemporaryOn::Fmt2(d)) => (2, [d[0], d[1], 0, 0]),
+            Cmd::Service(serv) => (0, [serv as u8, 0, 0, 0]),
+            Cmd::ClearMemory(_) => (4, CLEAR_MEMORY_KEY),
+            Cmd::Unknown { fmt, data, .. } => (fmt, data),
+            Cmd::Off
+            | Cmd::BrightDown
+            | Cmd::On
+            | Cmd::BrightUp
+            | Cmd::Switch
+            | Cmd::BrightBack
+            | Cmd::LoadPreset
+            | Cmd::SavePreset
+            | Cmd::Unbind
+            | Cmd::StopBright
+            | Cmd::BrightStepDown
+            | Cmd::BrightStepUp
+            | Cmd::Bind
+            | Cmd::RollColor
+            | Cmd::SwitchColor
+            | Cmd::SwitchMode
+            | Cmd::SpeedMode
+            | Cmd::BatteryLow
+            | Cmd::SensTempHumi
+            | Cmd::Modes
+            | Cmd::ReadState
+            | Cmd::WriteState
+            | Cmd::SendState => (0, [0; 4]),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::convert::TryFrom;
+
+    use crate::cmd::{ClearKey, Cmd, SetBrightness, TemporaryOn};
+
+    /// Every command variant, with payloads spread over the byte range.
+    pub fn all_cmds() -> Vec<Cmd> {
+        let mut cmds = vec![
+            Cmd::Off,
+            Cmd::BrightDown,
+            Cmd::On,
+            Cmd::BrightUp,
+            Cmd::Switch,
+            Cmd::BrightBack,
+            Cmd::LoadPreset,
+            Cmd::SavePreset,
+            Cmd::Unbind,
+            Cmd::StopBright,
+            Cmd::BrightStepDown,
+            Cmd::BrightStepUp,
+            Cmd::Bind,
+            Cmd::RollColor,
+            Cmd::SwitchColor,
+            Cmd::SwitchMode,
+            Cmd::SpeedMode,
+            Cmd::BatteryLow,
+            Cmd::SensTempHumi,
+            Cmd::Modes,
+            Cmd::ReadState,
+            Cmd::WriteState,
+            Cmd::SendState,
+            Cmd::Service(false),
+            Cmd::Service(true),
+            Cmd::ClearMemory(ClearKey(())),
+            Cmd::Unknown {
+                cmd: 14,
+                fmt: 0,
+                data: [0; 4],
+            },
+            Cmd::Unknown {
+                cmd: 6,
+                fmt: 2,
+                data: [1, 2, 3, 4],
+            },
+            Cmd::Unknown {
+                cmd: 25,
+                fmt: 9,
+                data: [5, 0, 0, 0],
+            },
+            Cmd::Unknown {
+                cmd: 132,
+                fmt: 4,
+                data: [0; 4],
+            },
+        ];
+        for val in (0..=255u8).step_by(15) {
+            let rev = 255 - val;
+            cmds.push(Cmd::SetBrightness(SetBrightness::Fmt1(val)));
+            cmds.push(Cmd::SetBrightness(SetBrightness::Fmt3([val, rev, val / 2])));
+            cmds.push(Cmd::BrightReg(val));
+            cmds.push(Cmd::TemporaryOn(TemporaryOn::Fmt1(val)));
+            cmds.push(Cmd::TemporaryOn(TemporaryOn::Fmt2([val, rev])));
+        }
+        cmds
+    }
+
+    #[test]
+    pub fn cmd_payload_round_trip() {
+        for cmd in all_cmds() {
+            let (fmt, data) = cmd.payload();
+            let bytes = [cmd.as_u8(), fmt, data[0], data[1], data[2], data[3]];
+            assert_eq!(Cmd::try_from(&bytes[..]).unwrap(), cmd);
+            assert!(Cmd::try_from(&bytes[..5]).is_err());
         }
     }
 }
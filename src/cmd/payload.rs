@@ -0,0 +1,107 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::cmd::sensor::TempHumiReading;
+use crate::cmd::state::DeviceState;
+use crate::cmd::Cmd;
+
+/// Step of the `TemporaryOn` timer.
+pub const TIMER_STEP: Duration = Duration::from_secs(5);
+
+/// FMT and D0–D3 of a frame decoded according to its command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Payload {
+    /// FMT 0 with all data bytes zeroed.
+    Empty,
+    /// `SetBrightness` FMT 1 and `BrightReg` FMT 1: D0.
+    Level(u8),
+    /// `SetBrightness` FMT 3: the colour of an RGB controller.
+    Color { r: u8, g: u8, b: u8 },
+    /// `TemporaryOn` FMT 1/2: how long the load stays on.
+    Timer(Duration),
+    /// `Service` FMT 0: whether service mode is turned on.
+    Service(bool),
+    /// `SendState` FMT 0/1/2.
+    State(DeviceState),
+    /// `SensTempHumi`: a sensor reading. Only [`Response::payload`] decodes
+    /// it, `Payload::decode` has no channel for the reading.
+    ///
+    /// [`Response::payload`]: crate::cmd::response::Response::payload
+    TempHumi(TempHumiReading),
+    /// Anything without a typed representation, kept as received.
+    Raw { fmt: u8, data: [u8; 4] },
+}
+
+impl Payload {
+    pub fn decode(cmd: &Cmd, fmt: u8, data: [u8; 4]) -> Payload {
+        let [d0, d1, d2, _] = data;
+        match (cmd, fmt) {
+            (Cmd::SetBrightness(_), 1) | (Cmd::BrightReg(_), 1) => Payload::Level(d0),
+            (Cmd::SetBrightness(_), 3) => Payload::Color {
+                r: d0,
+                g: d1,
+                b: d2,
+            },
+            (Cmd::TemporaryOn(_), 1) => Payload::Timer(TIMER_STEP * d0 as u32),
+            (Cmd::TemporaryOn(_), 2) => {
+                Payload::Timer(TIMER_STEP * u16::from_le_bytes([d0, d1]) as u32)
+            }
+            (Cmd::Service(_), 0) => Payload::Service(d0 == 1),
+            (Cmd::SendState, _) => match DeviceState::decode(fmt, data) {
+                Ok(state) => Payload::State(state),
+                Err(_) => Payload::Raw { fmt, data },
+            },
+            (_, 0) if data == [0; 4] => Payload::Empty,
+            _ => Payload::Raw { fmt, data },
+        }
+    }
+
+    /// Typed view of the payload `cmd` is encoded with.
+    pub fn of(cmd: &Cmd) -> Payload {
+        let (fmt, data) = cmd.payload();
+        Payload::decode(cmd, fmt, data)
+    }
+}
+
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Payload::Empty => write!(f, "-"),
+            Payload::Level(level) => write!(f, "Level({})", level),
+            Payload::Color { r, g, b } => write!(f, "Color({}, {}, {})", r, g, b),
+            Payload::Timer(time) => write!(f, "Timer({}s)", time.as_secs()),
+            Payload::Service(on) => write!(f, "Service({})", on),
+            Payload::State(state) => write!(f, "State({})", state),
+            Payload::TempHumi(reading) => write!(f, "TempHumi({})", reading),
+            Payload::Raw { fmt, data } => write!(f, "FMT:{} D:{:?}", fmt, data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::cmd::payload::Payload;
+    use crate::cmd::{Cmd, SetBrightness, TemporaryOn};
+
+    #[test]
+    pub fn typed_payloads() {
+        assert_eq!(Payload::of(&Cmd::On), Payload::Empty);
+        assert_eq!(
+            Payload::of(&Cmd::SetBrightness(SetBrightness::Fmt3([1, 2, 3]))),
+            Payload::Color { r: 1, g: 2, b: 3 }
+        );
+        assert_eq!(
+            Payload::of(&Cmd::TemporaryOn(TemporaryOn::Fmt2([0x2C, 0x01]))),
+            Payload::Timer(Duration::from_secs(1500))
+        );
+        assert_eq!(
+            Payload::decode(&Cmd::SendState, 7, [1, 2, 3, 4]),
+            Payload::Raw {
+                fmt: 7,
+                data: [1, 2, 3, 4]
+            }
+        );
+    }
+}
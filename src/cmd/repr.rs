@@ -0,0 +1,493 @@
+//! Stable, human-readable representations of the protocol types, e.g.
+//! `{"cmd": "set_brightness", "level": 128}` and `"mode": "txf"`.
+//!
+//! The serde impls of the protocol types are behind the `serde` feature, the
+//! bridges and the CLI build their JSON from the same representations.
+//!
+//! JSON schemas:
+//! - `Mode`: `"tx" | "rx" | "txf" | "rxf" | "service" | "firmware_update"`,
+//! - `CtrRequest`: `"send_command" | "send_broadcast_command" | "read_response" |
+//!   "bind_mode_on" | "bind_mode_off" | "clear_channel" | "clear_memory" |
+//!   "unbind_address_from_channel" | "send_command_to_id_in_channel" |
+//!   "send_command_to_id"`,
+//! - `CtrResponse`: `"success" | "no_response" | "error" | "bind_success"`,
+//! - `Cmd`: `{"cmd": <Cmd::name>}` with the fields of its payload:
+//!   - `set_brightness`: `"level": 0..=255` or `"color": {"r", "g", "b"}`,
+//!   - `bright_reg`: `"level": 0..=255`,
+//!   - `temporary_on`: `"seconds"`, rounded down to the 5 s step of the timer,
+//!   - `service`: `"on": bool`,
+//!   - `unknown`: `"code"`, the command byte, the payload is in the `fmt` and
+//!     `data` of a `Response`.
+//!
+//!   `clear_memory` and `unknown` are written but only read back as part of a
+//!   `Response`, see [`crate::clear`],
+//! - `SetBrightness`: `{"level"}` or `{"color"}`, `TemporaryOn`: `{"seconds"}`,
+//! - `Request`: `{"mode", "ctr", "ch", "id"}` with the fields of its `Cmd`,
+//! - `Response`: `{"mode", "ctr", "togl", "ch", "fmt", "data": [D0, D1, D2, D3],
+//!   "id"}` with the fields of its `Cmd` and the decoded device state or sensor
+//!   reading, see [`Response::to_json`](crate::cmd::response::Response::to_json). The decoded fields are ignored when
+//!   reading and `crc` is computed from the other fields.
+
+use std::convert::TryFrom;
+
+use anyhow::Error;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::cmd::payload::TIMER_STEP;
+use crate::cmd::{Cmd, SetBrightness, TemporaryOn};
+
+/// Commands without a payload, looked up by name.
+const PLAIN_COMMANDS: [Cmd; 23] = [
+    Cmd::Off,
+    Cmd::BrightDown,
+    Cmd::On,
+    Cmd::BrightUp,
+    Cmd::Switch,
+    Cmd::BrightBack,
+    Cmd::LoadPreset,
+    Cmd::SavePreset,
+    Cmd::Unbind,
+    Cmd::StopBright,
+    Cmd::BrightStepDown,
+    Cmd::BrightStepUp,
+    Cmd::Bind,
+    Cmd::RollColor,
+    Cmd::SwitchColor,
+    Cmd::SwitchMode,
+    Cmd::SpeedMode,
+    Cmd::BatteryLow,
+    Cmd::SensTempHumi,
+    Cmd::Modes,
+    Cmd::ReadState,
+    Cmd::WriteState,
+    Cmd::SendState,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// A command with its payload as named fields.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CmdRepr {
+    pub cmd: String,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub args: CmdArgs,
+}
+
+/// Payload fields of a [`CmdRepr`], the ones a command does not take are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CmdArgs {
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub level: Option<u8>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub color: Option<Color>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub seconds: Option<u32>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub on: Option<bool>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub code: Option<u8>,
+}
+
+impl From<&Cmd> for CmdRepr {
+    fn from(cmd: &Cmd) -> Self {
+        let mut args = CmdArgs::default();
+        match *cmd {
+            Cmd::SetBrightness(SetBrightness::Fmt1(level)) | Cmd::BrightReg(level) => {
+                args.level = Some(level)
+            }
+            Cmd::SetBrightness(SetBrightness::Fmt3([r, g, b])) => {
+                args.color = Some(Color { r, g, b })
+            }
+            Cmd::TemporaryOn(timer) => args.seconds = Some(timer.seconds()),
+            Cmd::Service(on) => args.on = Some(on),
+            Cmd::Unknown { cmd, .. } => args.code = Some(cmd),
+            _ => {}
+        }
+        CmdRepr {
+            cmd: cmd.name().to_owned(),
+            args,
+        }
+    }
+}
+
+impl TryFrom<CmdRepr> for Cmd {
+    type Error = Error;
+
+    fn try_from(repr: CmdRepr) -> Result<Self, Error> {
+        let name = repr.cmd.as_str();
+        if let Some(cmd) = PLAIN_COMMANDS.iter().find(|cmd| cmd.name() == name) {
+            return Ok(*cmd);
+        }
+        let args = repr.args;
+        Ok(match name {
+            "set_brightness" => match (args.level, args.color) {
+                (Some(level), None) => Cmd::SetBrightness(SetBrightness::Fmt1(level)),
+                (None, Some(Color { r, g, b })) => {
+                    Cmd::SetBrightness(SetBrightness::Fmt3([r, g, b]))
+                }
+                _ => bail!("set_brightness takes either a level or a color"),
+            },
+            "bright_reg" => Cmd::BrightReg(
+                args.level
+                    .ok_or_else(|| anyhow!("bright_reg takes a level"))?,
+            ),
+            "temporary_on" => Cmd::TemporaryOn(TemporaryOn::from_seconds(
+                args.seconds
+                    .ok_or_else(|| anyhow!("temporary_on takes seconds"))?,
+            )?),
+            "service" => Cmd::Service(args.on.ok_or_else(|| anyhow!("service takes on"))?),
+            "clear_memory" => bail!("clear_memory is only sent by crate::clear"),
+            "unknown" => bail!("Unknown commands are only read from frames"),
+            _ => bail!("Unknown command: {}", name),
+        })
+    }
+}
+
+impl TemporaryOn {
+    /// How long the load stays on.
+    pub fn seconds(&self) -> u32 {
+        let steps = match *self {
+            TemporaryOn::Fmt1(steps) => steps as u32,
+            TemporaryOn::Fmt2(steps) => u16::from_le_bytes(steps) as u32,
+        };
+        steps * TIMER_STEP.as_secs() as u32
+    }
+
+    /// The shortest format holding `seconds`, rounded down to the timer step.
+    pub fn from_seconds(seconds: u32) -> Result<TemporaryOn, Error> {
+        let steps = seconds / TIMER_STEP.as_secs() as u32;
+        Ok(match u8::try_from(steps) {
+            Ok(steps) => TemporaryOn::Fmt1(steps),
+            Err(_) => TemporaryOn::Fmt2(
+                u16::try_from(steps)
+                    .map_err(|_| anyhow!("Too long: {} seconds", seconds))?
+                    .to_le_bytes(),
+            ),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+mod with_serde {
+    use std::convert::TryFrom;
+
+    use anyhow::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::cmd::repr::{CmdArgs, CmdRepr};
+    use crate::cmd::request::Request;
+    use crate::cmd::response::Response;
+    use crate::cmd::{crc, Cmd, CtrRequest, CtrResponse, Mode, SetBrightness, TemporaryOn};
+
+    #[derive(Serialize, Deserialize)]
+    struct RequestRepr {
+        mode: Mode,
+        ctr: CtrRequest,
+        ch: u8,
+        #[serde(flatten)]
+        cmd: CmdRepr,
+        id: u32,
+    }
+
+    impl From<&Request> for RequestRepr {
+        fn from(req: &Request) -> Self {
+            RequestRepr {
+                mode: req.mode,
+                ctr: req.ctr,
+                ch: req.ch,
+                cmd: CmdRepr::from(&req.cmd),
+                id: req.id,
+            }
+        }
+    }
+
+    impl TryFrom<RequestRepr> for Request {
+        type Error = Error;
+
+        fn try_from(repr: RequestRepr) -> Result<Self, Error> {
+            let mut req = Request {
+                mode: repr.mode,
+                ctr: repr.ctr,
+                cmd: Cmd::try_from(repr.cmd)?,
+                id: repr.id,
+                ..Default::default()
+            };
+            req.set_ch(repr.ch)?;
+            Ok(req)
+        }
+    }
+
+    /// Frame fields of a response, the decoded fields of `to_json` are ignored.
+    #[derive(Deserialize)]
+    struct ResponseRepr {
+        mode: Mode,
+        ctr: CtrResponse,
+        togl: u8,
+        ch: u8,
+        #[serde(flatten)]
+        cmd: CmdRepr,
+        fmt: u8,
+        data: [u8; 4],
+        id: u32,
+    }
+
+    impl TryFrom<ResponseRepr> for Response {
+        type Error = Error;
+
+        fn try_from(repr: ResponseRepr) -> Result<Self, Error> {
+            let cmd = match (repr.cmd.cmd.as_str(), repr.cmd.args.code) {
+                ("unknown", Some(cmd)) => Cmd::Unknown {
+                    cmd,
+                    fmt: repr.fmt,
+                    data: repr.data,
+                },
+                _ => Cmd::try_from(repr.cmd)?,
+            };
+            let mut resp = Response {
+                mode: repr.mode,
+                ctr: repr.ctr,
+                togl: repr.togl,
+                ch: repr.ch,
+                cmd,
+                fmt: repr.fmt,
+                data: repr.data,
+                id: repr.id,
+                crc: 0,
+            };
+            resp.crc = crc(&resp.to_message());
+            Ok(resp)
+        }
+    }
+
+    /// Written as `to_json`.
+    impl Serialize for Response {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.to_json().serialize(serializer)
+        }
+    }
+
+    /// Serializes by `name`, deserializes with `FromStr`.
+    macro_rules! by_name {
+        ($($ty:ty),*) => {$(
+            impl Serialize for $ty {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.serialize_str(self.name())
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let name = String::deserialize(deserializer)?;
+                    name.parse().map_err(serde::de::Error::custom)
+                }
+            }
+        )*};
+    }
+
+    by_name!(Mode, CtrRequest, CtrResponse);
+
+    /// Serializes through a representation, deserializes with `TryFrom` of it.
+    macro_rules! via_repr {
+        ($($ty:ty => $repr:ty),*) => {$(
+            impl Serialize for $ty {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    <$repr>::from(self).serialize(serializer)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let repr = <$repr>::deserialize(deserializer)?;
+                    <$ty>::try_from(repr).map_err(serde::de::Error::custom)
+                }
+            }
+        )*};
+    }
+
+    via_repr!(Cmd => CmdRepr, Request => RequestRepr);
+
+    impl<'de> Deserialize<'de> for Response {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let repr = ResponseRepr::deserialize(deserializer)?;
+            Response::try_from(repr).map_err(serde::de::Error::custom)
+        }
+    }
+
+    /// The payload fields of a command, e.g. `{"level": 128}`.
+    macro_rules! args_of {
+        ($($ty:ident => $name:literal),*) => {$(
+            impl Serialize for $ty {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    CmdRepr::from(&Cmd::$ty(*self)).args.serialize(serializer)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let repr = CmdRepr {
+                        cmd: $name.to_owned(),
+                        args: CmdArgs::deserialize(deserializer)?,
+                    };
+                    match Cmd::try_from(repr).map_err(serde::de::Error::custom)? {
+                        Cmd::$ty(payload) => Ok(payload),
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        )*};
+    }
+
+    args_of!(SetBrightness => "set_brightness", TemporaryOn => "temporary_on");
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use crate::cmd::repr::CmdRepr;
+    use crate::cmd::tests::all_cmds;
+    use crate::cmd::{Cmd, TemporaryOn};
+
+    #[test]
+    pub fn cmd_repr_round_trip() {
+        for cmd in all_cmds() {
+            let repr = CmdRepr::from(&cmd);
+            if let Cmd::ClearMemory(_) | Cmd::Unknown { .. } = cmd {
+                assert!(Cmd::try_from(repr).is_err());
+                continue;
+            }
+            let back = Cmd::try_from(repr.clone()).unwrap();
+            assert_eq!(CmdRepr::from(&back), repr);
+            if let Cmd::TemporaryOn(_) = cmd {
+                continue;
+            }
+            assert_eq!(back, cmd);
+        }
+
+        assert_eq!(
+            TemporaryOn::from_seconds(2000).unwrap(),
+            TemporaryOn::Fmt2(400u16.to_le_bytes())
+        );
+        assert!(TemporaryOn::from_seconds(5 * 65536).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn serde_round_trip() {
+        use serde_json::json;
+
+        assert_eq!(
+            json!(CmdRepr::from(&Cmd::TemporaryOn(TemporaryOn::Fmt1(12)))),
+            json!({ "cmd": "temporary_on", "seconds": 60 })
+        );
+        let unknown: CmdRepr = serde_json::from_value(json!({ "cmd": "dance" })).unwrap();
+        assert!(Cmd::try_from(unknown).is_err());
+        let both: CmdRepr = serde_json::from_value(
+            json!({ "cmd": "set_brightness", "level": 1, "color": { "r": 1, "g": 2, "b": 3 } }),
+        )
+        .unwrap();
+        assert!(Cmd::try_from(both).is_err());
+
+        use crate::cmd::request::Request;
+        use crate::cmd::response::Response;
+        use crate::cmd::{
+            crc, CtrRequest, CtrResponse, Mode, SetBrightness, CMD_INDEX, FMT_INDEX, MESSAGE_LENGTH,
+        };
+
+        let cmd = Cmd::SetBrightness(SetBrightness::Fmt1(128));
+        assert_eq!(
+            serde_json::to_value(cmd).unwrap(),
+            json!({ "cmd": "set_brightness", "level": 128 })
+        );
+        assert_eq!(
+            serde_json::to_value(SetBrightness::Fmt3([255, 0, 10])).unwrap(),
+            json!({ "color": { "r": 255, "g": 0, "b": 10 } })
+        );
+        assert_eq!(
+            serde_json::from_value::<TemporaryOn>(json!({ "seconds": 25 })).unwrap(),
+            TemporaryOn::Fmt1(5)
+        );
+        assert_eq!(serde_json::to_value(Mode::TxF).unwrap(), json!("txf"));
+        assert_eq!(
+            serde_json::from_value::<Mode>(json!("firmware_update")).unwrap(),
+            Mode::FirmwareUpdate
+        );
+        assert_eq!(
+            serde_json::to_value(CtrRequest::SendCommandToId).unwrap(),
+            json!("send_command_to_id")
+        );
+        assert!(serde_json::from_value::<CtrResponse>(json!("maybe")).is_err());
+
+        let req = Request {
+            mode: Mode::TxF,
+            ctr: CtrRequest::SendCommandToId,
+            ch: 3,
+            cmd,
+            id: 0xAB,
+        };
+        let json = serde_json::to_value(req).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "mode": "txf",
+                "ctr": "send_command_to_id",
+                "ch": 3,
+                "cmd": "set_brightness",
+                "level": 128,
+                "id": 0xAB,
+            })
+        );
+        assert_eq!(serde_json::from_value::<Request>(json).unwrap(), req);
+        let bad_ch = json!({ "mode": "tx", "ctr": "send_command", "ch": 64, "cmd": "on", "id": 0 });
+        assert!(serde_json::from_value::<Request>(bad_ch).is_err());
+
+        // A `SendState` answer of a TX-F device.
+        let mut msg = [0; MESSAGE_LENGTH];
+        msg[..15].copy_from_slice(&[173, 2, 0, 0, 3, 130, 0, 2, 2, 0, 255, 0xAB, 0, 0, 0]);
+        msg[15] = crc(&msg);
+        msg[16] = 174;
+        let resp = Response::try_from(msg).unwrap();
+        assert_eq!(resp.ctr, CtrResponse::Success);
+        let json = serde_json::to_value(resp).unwrap();
+        assert_eq!(json, resp.to_json());
+        assert_eq!(json["cmd"], json!("send_state"));
+        assert_eq!(json["mode"], json!("txf"));
+        assert!(json.get("device_type").is_some());
+        assert_eq!(serde_json::from_value::<Response>(json).unwrap(), resp);
+
+        // A `TemporaryOn` in a format this crate does not decode.
+        msg[CMD_INDEX] = 25;
+        msg[FMT_INDEX] = 3;
+        msg[15] = crc(&msg);
+        let resp = Response::try_from(msg).unwrap();
+        let json = serde_json::to_value(resp).unwrap();
+        assert_eq!(json["cmd"], json!("unknown"));
+        assert_eq!(json["code"], json!(25));
+        assert_eq!(json["fmt"], json!(3));
+        assert_eq!(serde_json::from_value::<Response>(json).unwrap(), resp);
+    }
+}
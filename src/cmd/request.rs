@@ -1,17 +1,17 @@
+use std::convert::TryFrom;
 use std::fmt;
 
 use anyhow::Error;
 
+use crate::cmd::address::Address;
 use crate::cmd::{
-    Cmd, CtrRequest, Mode, SetBrightness, TemporaryOn, CH_INDEX, CMD_INDEX, CRC_INDEX,
-    MESSAGE_LENGTH,
+    crc, Cmd, CtrRequest, Mode, CHANNELS, CH_INDEX, CMD_INDEX, CRC_INDEX, DATA_INDEX, FMT_INDEX,
+    ID_INDEX, MESSAGE_LENGTH, REQUEST_SP as SP, REQUEST_ST as ST,
 };
 
-const ST: u8 = 171;
-const SP: u8 = 172;
 const RES: u8 = 0;
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Request {
     pub mode: Mode,
     pub ctr: CtrRequest,
@@ -26,11 +26,15 @@ impl Request {
     }
 
     pub fn set_ch(&mut self, ch: u8) -> Result<(), Error> {
-        ensure!(ch < 64, "The ch value must be between 0 and 63");
+        ensure!(ch < CHANNELS, "The ch value must be between 0 and 63");
         self.ch = ch;
         Ok(())
     }
 
+    pub fn address(&self) -> Address {
+        Address::of(self)
+    }
+
     pub fn to_message(self) -> [u8; MESSAGE_LENGTH] {
         let mut msg = [0; MESSAGE_LENGTH];
         msg[0] = ST;
@@ -41,89 +45,46 @@ impl Request {
         msg[CH_INDEX] = self.ch;
 
         msg[CMD_INDEX] = self.cmd.as_u8();
-        match self.cmd {
-            Cmd::SetBrightness(br) => match br {
-                SetBrightness::Fmt1(d0) => {
-                    msg[6] = 1;
-                    msg[7] = d0;
-                }
-                SetBrightness::Fmt3(d) => {
-                    msg[6] = 3;
-                    msg[7] = d[0];
-                    msg[8] = d[1];
-                    msg[9] = d[2];
-                }
-            },
-            Cmd::BrightReg(reg) => {
-                msg[6] = 1;
-                msg[7] = reg;
-            }
-            Cmd::TemporaryOn(tem) => match tem {
-                TemporaryOn::Fmt1(d0) => {
-                    msg[6] = 1;
-                    msg[7] = d0;
-                }
-                TemporaryOn::Fmt2(d) => {
-                    msg[6] = 2;
-                    msg[7] = d[0];
-                    msg[8] = d[1];
-                }
-            },
-            Cmd::Service(serv) => {
-                msg[7] = if serv { 1 } else { 0 };
-            }
-            Cmd::ClearMemory => {
-                msg[6] = 4;
-                msg[7] = 170;
-                msg[8] = 85;
-                msg[9] = 170;
-                msg[10] = 85;
-            }
-            Cmd::Off
-            | Cmd::BrightDown
-            | Cmd::On
-            | Cmd::BrightUp
-            | Cmd::Switch
-            | Cmd::BrightBack
-            | Cmd::LoadPreset
-            | Cmd::SavePreset
-            | Cmd::Unbind
-            | Cmd::StopBright
-            | Cmd::BrightStepDown
-            | Cmd::BrightStepUp
-            | Cmd::Bind
-            | Cmd::RollColor
-            | Cmd::SwitchColor
-            | Cmd::SwitchMode
-            | Cmd::SpeedMode
-            | Cmd::BatteryLow
-            | Cmd::SensTempHumi
-            | Cmd::Modes
-            | Cmd::ReadState
-            | Cmd::WriteState
-            | Cmd::SendState => {
-                // default parameters
-            }
-        }
-
-        let mut idx = 11;
-        for b in self.id.to_le_bytes().iter() {
-            msg[idx] = *b;
-            idx += 1;
-        }
-
-        let mut sum: u32 = 0;
-        for byte in msg.iter().take(15) {
-            sum += *byte as u32;
-        }
+        let (fmt, data) = self.cmd.payload();
+        msg[FMT_INDEX] = fmt;
+        msg[DATA_INDEX..DATA_INDEX + 4].copy_from_slice(&data);
 
-        msg[CRC_INDEX] = sum.to_le_bytes()[0];
+        msg[ID_INDEX..ID_INDEX + 4].copy_from_slice(&self.id.to_le_bytes());
+        msg[CRC_INDEX] = crc(&msg);
         msg[16] = SP;
 
         msg
     }
 }
 
+impl TryFrom<[u8; MESSAGE_LENGTH]> for Request {
+    type Error = Error;
+
+    fn try_from(value: [u8; MESSAGE_LENGTH]) -> Result<Self, Self::Error> {
+        ensure!(
+            value[0] == ST && value[16] == SP,
+            "Invalid request frame:[{:?}]",
+            value
+        );
+        ensure!(
+            crc(&value) == value[CRC_INDEX],
+            "Invalid message crc:[{:?}]",
+            value
+        );
+
+        let mut id = [0; 4];
+        id.copy_from_slice(&value[ID_INDEX..ID_INDEX + 4]);
+
+        Ok(Request {
+            mode: Mode::try_from(value[1])?,
+            ctr: CtrRequest::try_from(value[2])?,
+            ch: value[CH_INDEX],
+            cmd: Cmd::try_from(&value[CMD_INDEX..])?,
+            id: u32::from_le_bytes(id),
+        })
+    }
+}
+
 impl fmt::Display for Request {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -153,14 +114,18 @@ pub fn bind(md: Mode, ch: u8) -> Request {
 
 #[cfg(test)]
 mod test {
-    use crate::cmd::request::Request;
+    use std::convert::TryFrom;
+
+    use crate::cmd::request::{bind, Request};
+    use crate::cmd::tests::all_cmds;
     use crate::cmd::*;
 
     #[test]
     pub fn test_crc() {
-        let mut req = Request::default();
-
-        req.mode = Mode::TxF;
+        let mut req = Request {
+            mode: Mode::TxF,
+            ..Default::default()
+        };
         req.set_ch(5).unwrap();
         req.cmd = Cmd::Bind;
 
@@ -175,4 +140,49 @@ mod test {
             req.to_message()
         );
     }
+    #[test]
+    pub fn round_trip() {
+        let ctrs = [
+            CtrRequest::SendCommand,
+            CtrRequest::SendBroadcastCommand,
+            CtrRequest::ReadResponse,
+            CtrRequest::BindModeOn,
+            CtrRequest::BindModeOff,
+            CtrRequest::ClearChannel,
+            CtrRequest::ClearMemory,
+            CtrRequest::UnbindAddressFromChannel,
+            CtrRequest::SendCommandToIdInChannel,
+            CtrRequest::SendCommandToId,
+        ];
+        for (i, cmd) in all_cmds().into_iter().enumerate() {
+            let req = Request {
+                mode: Mode::try_from((i % 6) as u8).unwrap(),
+                ctr: ctrs[i % ctrs.len()],
+                ch: (i % 64) as u8,
+                cmd,
+                id: (i as u32).wrapping_mul(0x9E37_79B9),
+            };
+            let msg = req.to_message();
+            assert_eq!(Request::try_from(msg).unwrap(), req);
+            assert_eq!(Request::try_from(msg).unwrap().to_message(), msg);
+        }
+    }
+
+    #[test]
+    pub fn reject_corrupted() {
+        let msg = bind(Mode::TxF, 3).to_message();
+
+        let mut bad_crc = msg;
+        bad_crc[CH_INDEX] = 4;
+        assert!(Request::try_from(bad_crc).is_err());
+
+        let mut bad_st = msg;
+        bad_st[0] = RESPONSE_ST;
+        bad_st[CRC_INDEX] = crc(&bad_st);
+        assert!(Request::try_from(bad_st).is_err());
+
+        let mut bad_sp = msg;
+        bad_sp[16] = RESPONSE_SP;
+        assert!(Request::try_from(bad_sp).is_err());
+    }
 }
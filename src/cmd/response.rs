@@ -2,16 +2,29 @@ use std::convert::TryFrom;
 use std::fmt;
 
 use anyhow::Error;
+#[cfg(feature = "serde")]
+use serde_json::{json, Value};
 
-use crate::cmd::{Cmd, CtrResponse, Mode, CH_INDEX, CRC_INDEX, MESSAGE_LENGTH};
+use crate::cmd::payload::Payload;
+#[cfg(feature = "serde")]
+use crate::cmd::repr::CmdRepr;
+use crate::cmd::sensor::TempHumiReading;
+use crate::cmd::{
+    crc, Cmd, CtrResponse, Mode, CH_INDEX, CMD_INDEX, CRC_INDEX, DATA_INDEX, FMT_INDEX, ID_INDEX,
+    MESSAGE_LENGTH, RESPONSE_SP, RESPONSE_ST,
+};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Response {
     pub mode: Mode,
     pub ctr: CtrResponse,
     pub togl: u8,
     pub ch: u8,
     pub cmd: Cmd,
+    /// Raw FMT byte, see [`Response::payload`] for the decoded form.
+    pub fmt: u8,
+    /// Raw D0–D3 bytes.
+    pub data: [u8; 4],
     pub id: u32,
     pub crc: u8,
 }
@@ -20,30 +33,92 @@ impl TryFrom<[u8; MESSAGE_LENGTH]> for Response {
     type Error = Error;
 
     fn try_from(value: [u8; 17]) -> Result<Self, Self::Error> {
-        let sum: u32 = value.iter().take(15).map(|b| *b as u32).sum();
-
-        if sum.to_le_bytes()[0] != value[CRC_INDEX] {
+        ensure!(
+            value[0] == RESPONSE_ST && value[16] == RESPONSE_SP,
+            "Invalid response frame:[{:?}]",
+            value
+        );
+        if crc(&value) != value[CRC_INDEX] {
             return Err(anyhow!("Invalid message crc:[{:?}]", value));
         }
 
+        let mut data = [0; 4];
+        data.copy_from_slice(&value[DATA_INDEX..DATA_INDEX + 4]);
         let mut id = [0; 4];
-        id[0] = value[11];
-        id[1] = value[12];
-        id[2] = value[13];
-        id[3] = value[14];
+        id.copy_from_slice(&value[ID_INDEX..ID_INDEX + 4]);
 
         Ok(Response {
             mode: Mode::try_from(value[1])?,
             ctr: CtrResponse::try_from(value[2])?,
             togl: value[3],
             ch: value[CH_INDEX],
-            cmd: Cmd::try_from(&value[5..])?,
+            cmd: Cmd::try_from(&value[CMD_INDEX..])?,
+            fmt: value[FMT_INDEX],
+            data,
             id: u32::from_le_bytes(id),
             crc: value[CRC_INDEX],
         })
     }
 }
 
+impl Response {
+    /// FMT and D0–D3 decoded according to the command, a `SensTempHumi`
+    /// reading included.
+    pub fn payload(&self) -> Payload {
+        match TempHumiReading::try_from(self) {
+            Ok(reading) => Payload::TempHumi(reading),
+            Err(_) => Payload::decode(&self.cmd, self.fmt, self.data),
+        }
+    }
+
+    /// JSON object with the frame fields, the fields of the command and the
+    /// decoded device state or sensor reading, see [`crate::cmd::repr`].
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Value {
+        let mut json = json!(CmdRepr::from(&self.cmd));
+        json["mode"] = json!(self.mode.name());
+        json["ctr"] = json!(self.ctr.name());
+        json["togl"] = json!(self.togl);
+        json["ch"] = json!(self.ch);
+        json["fmt"] = json!(self.fmt);
+        json["data"] = json!(self.data);
+        json["id"] = json!(self.id);
+        match self.payload() {
+            Payload::State(state) => {
+                if let (Value::Object(json), Value::Object(state)) = (&mut json, state.to_json()) {
+                    json.extend(state);
+                }
+            }
+            Payload::TempHumi(reading) => {
+                json["temperature"] = json!(reading.celsius());
+                json["humidity"] = json!(reading.humidity);
+                json["battery_low"] = json!(reading.battery_low);
+                json["analog"] = json!(reading.analog);
+            }
+            _ => {}
+        }
+        json
+    }
+
+    /// Encodes the response the way the adapter sends it. The checksum is
+    /// computed from the other fields, `crc` is ignored.
+    pub fn to_message(&self) -> [u8; MESSAGE_LENGTH] {
+        let mut msg = [0; MESSAGE_LENGTH];
+        msg[0] = RESPONSE_ST;
+        msg[1] = self.mode as u8;
+        msg[2] = self.ctr as u8;
+        msg[3] = self.togl;
+        msg[CH_INDEX] = self.ch;
+        msg[CMD_INDEX] = self.cmd.as_u8();
+        msg[FMT_INDEX] = self.fmt;
+        msg[DATA_INDEX..DATA_INDEX + 4].copy_from_slice(&self.data);
+        msg[ID_INDEX..ID_INDEX + 4].copy_from_slice(&self.id.to_le_bytes());
+        msg[CRC_INDEX] = crc(&msg);
+        msg[16] = RESPONSE_SP;
+        msg
+    }
+}
+
 impl fmt::Display for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -51,9 +126,106 @@ impl fmt::Display for Response {
             "ST:173 MODE:{} CTR:{} TOGL:{} CH:{} ",
             self.mode, self.ctr, self.togl, self.ch
         )?;
-        write!(f, "CMD:{} ", self.cmd)?;
+        write!(f, "CMD:{} FMT:{} D:{:?} ", self.cmd, self.fmt, self.data)?;
         write!(f, "ID:{} ", self.id)?;
         write!(f, "CRC:{} ", self.crc)?;
         write!(f, "SP:174")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use crate::cmd::payload::Payload;
+    use crate::cmd::response::Response;
+    use crate::cmd::tests::all_cmds;
+    use crate::cmd::*;
+
+    #[test]
+    pub fn round_trip() {
+        let ctrs = [
+            CtrResponse::Success,
+            CtrResponse::NoResponse,
+            CtrResponse::Error,
+            CtrResponse::BindSuccess,
+        ];
+        for (i, cmd) in all_cmds().into_iter().enumerate() {
+            let (fmt, data) = cmd.payload();
+            let mut resp = Response {
+                mode: Mode::try_from((i % 6) as u8).unwrap(),
+                ctr: ctrs[i % ctrs.len()],
+                togl: (i % 128) as u8,
+                ch: (i % 64) as u8,
+                cmd,
+                fmt,
+                data,
+                id: (i as u32).wrapping_mul(0x9E37_79B9),
+                crc: 0,
+            };
+            let msg = resp.to_message();
+            resp.crc = msg[CRC_INDEX];
+            assert_eq!(Response::try_from(msg).unwrap(), resp);
+            assert_eq!(resp.to_message(), msg);
+        }
+    }
+
+    #[test]
+    pub fn reject_request_frame() {
+        let msg = crate::cmd::request::bind(Mode::TxF, 3).to_message();
+        assert!(Response::try_from(msg).is_err());
+    }
+    #[test]
+    pub fn keep_unknown_payload() {
+        let mut msg = [0; MESSAGE_LENGTH];
+        msg[0] = RESPONSE_ST;
+        msg[1] = Mode::RX as u8;
+        msg[CMD_INDEX] = Cmd::Switch.as_u8();
+        msg[FMT_INDEX] = 7;
+        msg[DATA_INDEX..DATA_INDEX + 4].copy_from_slice(&[1, 2, 3, 4]);
+        msg[CRC_INDEX] = crc(&msg);
+        msg[16] = RESPONSE_SP;
+
+        let resp = Response::try_from(msg).unwrap();
+        assert_eq!(resp.fmt, 7);
+        assert_eq!(resp.data, [1, 2, 3, 4]);
+        assert_eq!(
+            resp.payload(),
+            Payload::Raw {
+                fmt: 7,
+                data: [1, 2, 3, 4]
+            }
+        );
+        assert_eq!(resp.to_message(), msg);
+    }
+
+    #[test]
+    pub fn keep_unknown_fmt() {
+        let mut msg = [0; MESSAGE_LENGTH];
+        msg[0] = RESPONSE_ST;
+        msg[1] = Mode::TxF as u8;
+        msg[CMD_INDEX] = 6;
+        msg[FMT_INDEX] = 2;
+        msg[DATA_INDEX..DATA_INDEX + 4].copy_from_slice(&[10, 20, 0, 0]);
+        msg[CRC_INDEX] = crc(&msg);
+        msg[16] = RESPONSE_SP;
+
+        let resp = Response::try_from(msg).unwrap();
+        assert_eq!(
+            resp.cmd,
+            Cmd::Unknown {
+                cmd: 6,
+                fmt: 2,
+                data: [10, 20, 0, 0]
+            }
+        );
+        assert_eq!(
+            resp.payload(),
+            Payload::Raw {
+                fmt: 2,
+                data: [10, 20, 0, 0]
+            }
+        );
+        assert_eq!(resp.to_message(), msg);
+    }
+}
@@ -0,0 +1,137 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use anyhow::Error;
+
+use crate::cmd::response::Response;
+use crate::cmd::Cmd;
+
+/// Reading of a PT111/PT112 sensor, sent as `SensTempHumi` in RX mode.
+///
+/// The payload packs a 12-bit two's complement temperature into D0 and the
+/// low nibble of D1. The rest of D1 holds the device type (bits 4–6) and
+/// the battery-low flag (bit 7), D2 is the humidity and D3 the analog input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TempHumiReading {
+    /// Channel the sensor is bound to.
+    pub ch: u8,
+    /// Temperature in 0.1 °C.
+    pub temperature: i16,
+    /// Relative humidity in percent, 0 for sensors without a humidity probe.
+    pub humidity: u8,
+    pub device_type: u8,
+    pub battery_low: bool,
+    pub analog: u8,
+}
+
+impl TempHumiReading {
+    pub fn decode(ch: u8, data: [u8; 4]) -> TempHumiReading {
+        let [d0, d1, d2, d3] = data;
+        let raw = (((d1 & 0x0F) as u16) << 8) | d0 as u16;
+        // Sign-extend from 12 bits.
+        let temperature = ((raw << 4) as i16) >> 4;
+        TempHumiReading {
+            ch,
+            temperature,
+            humidity: d2,
+            device_type: (d1 >> 4) & 0x07,
+            battery_low: d1 & 0x80 != 0,
+            analog: d3,
+        }
+    }
+
+    pub fn celsius(&self) -> f32 {
+        self.temperature as f32 / 10.0
+    }
+}
+
+impl TryFrom<&Response> for TempHumiReading {
+    type Error = Error;
+
+    fn try_from(resp: &Response) -> Result<Self, Self::Error> {
+        ensure!(
+            resp.cmd == Cmd::SensTempHumi,
+            "Not a temperature/humidity message: {}",
+            resp
+        );
+        Ok(TempHumiReading::decode(resp.ch, resp.data))
+    }
+}
+
+impl fmt::Display for TempHumiReading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CH:{} {:.1}°C {}% type={} analog={}",
+            self.ch,
+            self.celsius(),
+            self.humidity,
+            self.device_type,
+            self.analog
+        )?;
+        if self.battery_low {
+            write!(f, " battery low")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use crate::cmd::payload::Payload;
+    use crate::cmd::request::bind;
+    use crate::cmd::sensor::TempHumiReading;
+    use crate::cmd::{Cmd, Mode};
+    use crate::emulator::Emulator;
+    use crate::mtrf::Mtrf;
+
+    #[test]
+    pub fn decode_temperature() {
+        let warm = TempHumiReading::decode(1, [0xE7, 0x10, 45, 200]);
+        assert_eq!(warm.temperature, 231);
+        assert_eq!(warm.humidity, 45);
+        assert_eq!(warm.device_type, 1);
+        assert!(!warm.battery_low);
+
+        // -5.5 °C is 0xFC9 in 12 bits.
+        let cold = TempHumiReading::decode(1, [0xC9, 0xAF, 0, 0]);
+        assert_eq!(cold.temperature, -55);
+        assert_eq!(cold.device_type, 2);
+        assert!(cold.battery_low);
+    }
+
+    #[test]
+    pub fn reading_from_sensor() {
+        let emulator = Emulator::new();
+        let (tx, rx) = channel();
+        let mtrf = Mtrf::with_transport(emulator.transport(), tx).unwrap();
+
+        let remote = emulator.clone();
+        let presser = std::thread::spawn(move || {
+            while remote.bind_mode().is_none() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            remote.press(Mode::RX, 9, Cmd::Bind);
+        });
+        mtrf.send_request(bind(Mode::RX, 6)).unwrap();
+        presser.join().unwrap();
+
+        emulator.press_raw(
+            Mode::RX,
+            9,
+            Cmd::SensTempHumi.as_u8(),
+            7,
+            [0xE7, 0x10, 45, 0],
+        );
+        let msg = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        let reading = TempHumiReading::try_from(&msg).unwrap();
+        assert_eq!(reading.ch, 6);
+        assert_eq!(reading.temperature, 231);
+        assert_eq!(reading.humidity, 45);
+        assert_eq!(msg.payload(), Payload::TempHumi(reading));
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+
+use anyhow::Error;
+#[cfg(feature = "serde")]
+use serde_json::{json, Value};
+
+/// Output of a power unit as reported in a `SendState` reply.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoadState {
+    Off = 0,
+    On = 1,
+    /// Turned on by `TemporaryOn` and waiting for the timer.
+    TemporaryOn = 2,
+}
+
+impl fmt::Display for LoadState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadState::Off => write!(f, "Off"),
+            LoadState::On => write!(f, "On"),
+            LoadState::TemporaryOn => write!(f, "TemporaryOn"),
+        }
+    }
+}
+
+/// Payload of a nooLite-F `SendState` reply, one variant per FMT.
+///
+/// Every format starts with the device type (D0) and firmware version (D1).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceState {
+    /// FMT 0: D2 is the load state, D3 the current brightness.
+    Main {
+        device_type: u8,
+        firmware: u8,
+        state: LoadState,
+        brightness: u8,
+    },
+    /// FMT 1: D2 is the state of the built-in input.
+    Extended {
+        device_type: u8,
+        firmware: u8,
+        input: u8,
+    },
+    /// FMT 2: D2 is the device specific settings bit field.
+    Settings {
+        device_type: u8,
+        firmware: u8,
+        settings: u8,
+    },
+}
+
+impl DeviceState {
+    pub fn decode(fmt: u8, data: [u8; 4]) -> Result<DeviceState, Error> {
+        let [device_type, firmware, d2, d3] = data;
+        Ok(match fmt {
+            0 => DeviceState::Main {
+                device_type,
+                firmware,
+                state: match d2 {
+                    0 => LoadState::Off,
+                    1 => LoadState::On,
+                    2 => LoadState::TemporaryOn,
+                    _ => return Err(anyhow!("Failed to decode load state:{}", d2)),
+                },
+                brightness: d3,
+            },
+            1 => DeviceState::Extended {
+                device_type,
+                firmware,
+                input: d2,
+            },
+            2 => DeviceState::Settings {
+                device_type,
+                firmware,
+                settings: d2,
+            },
+            _ => return Err(anyhow!("Unknown device state format:{}", fmt)),
+        })
+    }
+
+    pub fn device_type(&self) -> u8 {
+        match *self {
+            DeviceState::Main { device_type, .. }
+            | DeviceState::Extended { device_type, .. }
+            | DeviceState::Settings { device_type, .. } => device_type,
+        }
+    }
+
+    pub fn firmware(&self) -> u8 {
+        match *self {
+            DeviceState::Main { firmware, .. }
+            | DeviceState::Extended { firmware, .. }
+            | DeviceState::Settings { firmware, .. } => firmware,
+        }
+    }
+
+    /// JSON object with the device type, the firmware and the fields of the format.
+    /// The load `state` is `"ON"` or `"OFF"`, with `"temporary": true` while
+    /// a `TemporaryOn` timer runs.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Value {
+        let mut state = json!({
+            "device_type": self.device_type(),
+            "firmware": self.firmware(),
+        });
+        match *self {
+            DeviceState::Main {
+                state: load,
+                brightness,
+                ..
+            } => {
+                state["state"] = json!(if load == LoadState::Off { "OFF" } else { "ON" });
+                if load == LoadState::TemporaryOn {
+                    state["temporary"] = json!(true);
+                }
+                state["brightness"] = json!(brightness);
+            }
+            DeviceState::Extended { input, .. } => state["input"] = json!(input),
+            DeviceState::Settings { settings, .. } => state["settings"] = json!(settings),
+        }
+        state
+    }
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "type={} fw={} ", self.device_type(), self.firmware())?;
+        match self {
+            DeviceState::Main {
+                state, brightness, ..
+            } => write!(f, "state={} brightness={}", state, brightness),
+            DeviceState::Extended { input, .. } => write!(f, "input={}", input),
+            DeviceState::Settings { settings, .. } => write!(f, "settings={:#010b}", settings),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::state::{DeviceState, LoadState};
+
+    #[test]
+    pub fn decode_formats() {
+        assert_eq!(
+            DeviceState::decode(0, [5, 2, 2, 128]).unwrap(),
+            DeviceState::Main {
+                device_type: 5,
+                firmware: 2,
+                state: LoadState::TemporaryOn,
+                brightness: 128,
+            }
+        );
+        assert_eq!(
+            DeviceState::decode(2, [5, 2, 0b101, 0]).unwrap(),
+            DeviceState::Settings {
+                device_type: 5,
+                firmware: 2,
+                settings: 0b101,
+            }
+        );
+        assert!(DeviceState::decode(0, [5, 2, 7, 0]).is_err());
+        assert!(DeviceState::decode(3, [0; 4]).is_err());
+
+        #[cfg(feature = "serde")]
+        {
+            use serde_json::json;
+
+            let timer = DeviceState::decode(0, [5, 2, 2, 128]).unwrap();
+            assert_eq!(
+                timer.to_json(),
+                json!({
+                    "device_type": 5,
+                    "firmware": 2,
+                    "state": "ON",
+                    "temporary": true,
+                    "brightness": 128,
+                })
+            );
+            let on = DeviceState::decode(0, [5, 2, 1, 128]).unwrap();
+            assert!(on.to_json().get("temporary").is_none());
+        }
+    }
+}
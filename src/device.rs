@@ -0,0 +1,245 @@
+//! Typed handles for common nooLite devices.
+//!
+//! Each handle wraps an [`Mtrf`], a mode and an [`Address`] and turns its
+//! methods into requests, so callers do not assemble `Request`s by hand.
+
+use anyhow::Error;
+
+use crate::cmd::address::Address;
+use crate::cmd::request::Request;
+use crate::cmd::response::Response;
+use crate::cmd::{Cmd, CtrRequest, Mode, SetBrightness};
+use crate::error::RequestError;
+use crate::mtrf::Mtrf;
+
+/// Outcome of a device command the adapter accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    /// Transmitted in TX mode, which has no feedback from the device.
+    Sent,
+    /// Acknowledged by the addressed nooLite-F device, or by the adapter for
+    /// a broadcast.
+    Confirmed(Response),
+    /// Answers of the nooLite-F devices bound to the channel, one per device.
+    /// A device that did not answer is kept as its `NoResponse` packet.
+    PerDevice(Vec<Response>),
+}
+
+/// Maps 0–100 % to the 0–255 brightness of `SetBrightness`.
+pub fn brightness_from_percent(percent: u8) -> Option<u8> {
+    if percent > 100 {
+        return None;
+    }
+    Some(((percent as u16 * 255 + 50) / 100) as u8)
+}
+
+#[derive(Clone)]
+struct Handle {
+    mtrf: Mtrf,
+    /// Validated request every command is sent with.
+    req: Request,
+}
+
+impl Handle {
+    fn new(mtrf: Mtrf, mode: Mode, address: Address) -> Result<Handle, Error> {
+        ensure!(
+            mode == Mode::TX || mode == Mode::TxF,
+            "Devices are controlled in TX or TX-F mode, not {}",
+            mode
+        );
+        let req = address.request(mode, Cmd::Off)?;
+        Ok(Handle { mtrf, req })
+    }
+
+    fn send(&self, cmd: Cmd) -> Result<Delivery, RequestError> {
+        let req = Request { cmd, ..self.req };
+        Ok(match (req.mode, req.ctr) {
+            (Mode::TxF, CtrRequest::SendCommand) => {
+                Delivery::PerDevice(self.mtrf.send_request_all(req)?)
+            }
+            (Mode::TxF, _) => Delivery::Confirmed(self.mtrf.send_request(req)?),
+            _ => {
+                self.mtrf.send_request(req)?;
+                Delivery::Sent
+            }
+        })
+    }
+
+    fn address(&self) -> Address {
+        self.req.address()
+    }
+}
+
+/// On/off power unit.
+#[derive(Clone)]
+pub struct Relay(Handle);
+
+impl Relay {
+    pub fn new(mtrf: Mtrf, mode: Mode, address: Address) -> Result<Relay, Error> {
+        Ok(Relay(Handle::new(mtrf, mode, address)?))
+    }
+
+    pub fn address(&self) -> Address {
+        self.0.address()
+    }
+
+    pub fn on(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::On)
+    }
+
+    pub fn off(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::Off)
+    }
+
+    pub fn toggle(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::Switch)
+    }
+}
+
+/// Power unit with brightness control.
+#[derive(Clone)]
+pub struct Dimmer(Handle);
+
+impl Dimmer {
+    pub fn new(mtrf: Mtrf, mode: Mode, address: Address) -> Result<Dimmer, Error> {
+        Ok(Dimmer(Handle::new(mtrf, mode, address)?))
+    }
+
+    pub fn address(&self) -> Address {
+        self.0.address()
+    }
+
+    pub fn on(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::On)
+    }
+
+    pub fn off(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::Off)
+    }
+
+    /// Sets the brightness in percent, 0 turns the load off.
+    pub fn set_level(&self, percent: u8) -> Result<Delivery, RequestError> {
+        let level = brightness_from_percent(percent).ok_or(RequestError::InvalidRequest(
+            "The level must be between 0 and 100",
+        ))?;
+        self.0.send(Cmd::SetBrightness(SetBrightness::Fmt1(level)))
+    }
+
+    pub fn step_up(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::BrightStepUp)
+    }
+
+    pub fn step_down(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::BrightStepDown)
+    }
+
+    /// Stops a brightness change started by `BrightUp`/`BrightDown`.
+    pub fn stop(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::StopBright)
+    }
+}
+
+/// RGB LED controller.
+#[derive(Clone)]
+pub struct RgbController(Handle);
+
+impl RgbController {
+    pub fn new(mtrf: Mtrf, mode: Mode, address: Address) -> Result<RgbController, Error> {
+        Ok(RgbController(Handle::new(mtrf, mode, address)?))
+    }
+
+    pub fn address(&self) -> Address {
+        self.0.address()
+    }
+
+    pub fn on(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::On)
+    }
+
+    pub fn off(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::Off)
+    }
+
+    pub fn set_color(&self, r: u8, g: u8, b: u8) -> Result<Delivery, RequestError> {
+        self.0
+            .send(Cmd::SetBrightness(SetBrightness::Fmt3([r, g, b])))
+    }
+
+    /// Starts cycling through the colours.
+    pub fn roll_color(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::RollColor)
+    }
+
+    /// Jumps to the next of the preset colours.
+    pub fn switch_color(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::SwitchColor)
+    }
+
+    /// Switches between the preset animation modes.
+    pub fn switch_mode(&self) -> Result<Delivery, RequestError> {
+        self.0.send(Cmd::SwitchMode)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::address::Address;
+    use crate::cmd::request::bind;
+    use crate::cmd::{CtrResponse, Mode};
+    use crate::device::{Delivery, Dimmer, Relay};
+    use crate::emulator::{Emulator, SimDevice};
+    use crate::error::RequestError;
+    use crate::mtrf::tests::Logger;
+    use crate::mtrf::Mtrf;
+
+    #[test]
+    pub fn relay_and_dimmer() {
+        let emulator = Emulator::new();
+        emulator.add_device(SimDevice::new(0x10));
+        emulator.add_device(SimDevice::new(0x11));
+        let mtrf = Mtrf::with_transport(emulator.transport(), Logger).unwrap();
+        mtrf.send_request(bind(Mode::TxF, 1)).unwrap();
+        mtrf.send_request(bind(Mode::TxF, 1)).unwrap();
+
+        let relay = Relay::new(mtrf.clone(), Mode::TxF, Address::Channel(1)).unwrap();
+        match relay.on().unwrap() {
+            Delivery::PerDevice(answers) => {
+                let ids: Vec<u32> = answers.iter().map(|resp| resp.id).collect();
+                assert_eq!(ids, vec![0x10, 0x11]);
+            }
+            delivery => panic!("Unexpected delivery: {:?}", delivery),
+        }
+        assert!(emulator.device(0x10).unwrap().on);
+        assert!(emulator.device(0x11).unwrap().on);
+        emulator.update_device(0x11, |dev| dev.online = false);
+        match relay.toggle().unwrap() {
+            Delivery::PerDevice(answers) => {
+                let ctrs: Vec<CtrResponse> = answers.iter().map(|resp| resp.ctr).collect();
+                assert_eq!(ctrs, vec![CtrResponse::Success, CtrResponse::NoResponse]);
+            }
+            delivery => panic!("Unexpected delivery: {:?}", delivery),
+        }
+        assert!(!emulator.device(0x10).unwrap().on);
+
+        let dimmer = Dimmer::new(mtrf.clone(), Mode::TxF, Address::Id(0x10)).unwrap();
+        match dimmer.set_level(50).unwrap() {
+            Delivery::Confirmed(resp) => assert_eq!(resp.id, 0x10),
+            delivery => panic!("Unexpected delivery: {:?}", delivery),
+        }
+        assert_eq!(emulator.device(0x10).unwrap().brightness, 128);
+        match dimmer.set_level(101) {
+            Err(RequestError::InvalidRequest(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let unbound = Relay::new(mtrf.clone(), Mode::TxF, Address::Channel(2)).unwrap();
+        match unbound.on() {
+            Err(RequestError::DeviceNoResponse(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        assert!(Relay::new(mtrf.clone(), Mode::TX, Address::Id(0x10)).is_err());
+        let tx = Relay::new(mtrf, Mode::TX, Address::Channel(2)).unwrap();
+        assert_eq!(tx.on().unwrap(), Delivery::Sent);
+    }
+}
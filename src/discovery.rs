@@ -0,0 +1,227 @@
+//! Lookup of serial devices that may have an MTRF-64 adapter behind them.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+
+use crate::cmd::request::set_mode;
+use crate::cmd::response::Response;
+use crate::cmd::{Mode, MESSAGE_LENGTH};
+use crate::transport::{SerialTransport, Transport};
+
+/// USB vendor id of FTDI, whose FT232 bridge is used by MTRF-64-USB.
+pub const FTDI_VENDOR_ID: u16 = 0x0403;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortInfo {
+    /// Device node, e.g. `/dev/ttyUSB0`.
+    pub path: PathBuf,
+    /// Stable `/dev/serial/by-id` alias of the device, if udev created one.
+    pub by_id: Option<PathBuf>,
+    pub usb: Option<UsbInfo>,
+}
+
+impl PortInfo {
+    pub fn is_ftdi(&self) -> bool {
+        self.usb
+            .as_ref()
+            .map(|usb| usb.vendor_id == FTDI_VENDOR_ID)
+            .unwrap_or(false)
+    }
+}
+
+/// Lists candidate serial devices, FTDI bridges first.
+pub fn list_ports() -> Result<Vec<PortInfo>, Error> {
+    scan(Path::new("/dev"), Path::new("/sys/class/tty"))
+}
+
+/// Paths of the candidate serial devices, FTDI bridges first.
+pub fn ports() -> Result<Vec<String>, Error> {
+    Ok(list_ports()?
+        .into_iter()
+        .map(|port| port.path.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Candidates that answered a probe.
+pub fn find_adapters(timeout: Duration) -> Result<Vec<PortInfo>, Error> {
+    Ok(list_ports()?
+        .into_iter()
+        .filter(|port| match probe(&port.path.to_string_lossy(), timeout) {
+            Ok(found) => found,
+            Err(err) => {
+                debug!("Failed to probe {}: {}", port.path.display(), err);
+                false
+            }
+        })
+        .collect())
+}
+
+/// Checks whether an MTRF-64 answers on the given serial port.
+pub fn probe(port_name: &str, timeout: Duration) -> Result<bool, Error> {
+    let mut transport = SerialTransport::open(port_name)?;
+    transport.configure()?;
+    probe_transport(&mut transport, timeout)
+}
+
+/// Sends `set_mode(Mode::RX)` and waits for a well-formed answer. RX mode
+/// does not transmit anything on air, so the request is harmless.
+pub fn probe_transport<T: Transport>(transport: &mut T, timeout: Duration) -> Result<bool, Error> {
+    transport.set_timeout(Duration::from_millis(20))?;
+    transport.write_frame(&set_mode(Mode::RX).to_message())?;
+
+    let deadline = Instant::now() + timeout;
+    let mut msg = [0; MESSAGE_LENGTH];
+    while Instant::now() < deadline {
+        if transport.read_frame(&mut msg)? {
+            return Ok(Response::try_from(msg)
+                .map(|resp| resp.mode == Mode::RX)
+                .unwrap_or(false));
+        }
+    }
+    Ok(false)
+}
+
+fn scan(dev_root: &Path, sys_root: &Path) -> Result<Vec<PortInfo>, Error> {
+    let mut ports: Vec<PortInfo> = vec![];
+
+    let by_id_dir = dev_root.join("serial").join("by-id");
+    if let Ok(entries) = fs::read_dir(&by_id_dir) {
+        for entry in entries.flatten() {
+            let alias = entry.path();
+            if let Ok(path) = fs::canonicalize(&alias) {
+                if !ports.iter().any(|port| port.path == path) {
+                    ports.push(PortInfo {
+                        path,
+                        by_id: Some(alias),
+                        usb: None,
+                    });
+                }
+            }
+        }
+    }
+
+    for entry in fs::read_dir(dev_root)?.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !name.starts_with("ttyUSB") && !name.starts_with("ttyACM") {
+            continue;
+        }
+        let path = fs::canonicalize(entry.path()).unwrap_or_else(|_| entry.path());
+        if !ports.iter().any(|port| port.path == path) {
+            ports.push(PortInfo {
+                path,
+                by_id: None,
+                usb: None,
+            });
+        }
+    }
+
+    for port in ports.iter_mut() {
+        if let Some(name) = port.path.file_name() {
+            port.usb = usb_info(&sys_root.join(name).join("device"));
+        }
+    }
+
+    ports.sort_by(|a, b| b.is_ftdi().cmp(&a.is_ftdi()).then(a.path.cmp(&b.path)));
+    Ok(ports)
+}
+
+/// Walks up from the tty's sysfs device to the USB device that owns it.
+fn usb_info(device: &Path) -> Option<UsbInfo> {
+    let mut dir = fs::canonicalize(device).ok()?;
+    loop {
+        if dir.join("idVendor").exists() {
+            return Some(UsbInfo {
+                vendor_id: read_hex(&dir.join("idVendor"))?,
+                product_id: read_hex(&dir.join("idProduct"))?,
+                serial: read_attr(&dir.join("serial")),
+                manufacturer: read_attr(&dir.join("manufacturer")),
+                product: read_attr(&dir.join("product")),
+            });
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+fn read_attr(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|val| val.trim().to_owned())
+}
+
+fn read_hex(path: &Path) -> Option<u16> {
+    u16::from_str_radix(&read_attr(path)?, 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::time::Duration;
+
+    use crate::discovery::{probe_transport, scan, FTDI_VENDOR_ID};
+    use crate::emulator::Emulator;
+    use crate::transport::Loopback;
+
+    #[test]
+    pub fn scan_fake_tree() {
+        let root = std::env::temp_dir().join(format!("mtrf-scan-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dev = root.join("dev");
+        let sys = root.join("sys");
+        fs::create_dir_all(dev.join("serial/by-id")).unwrap();
+        fs::write(dev.join("ttyACM0"), b"").unwrap();
+        fs::write(dev.join("ttyUSB0"), b"").unwrap();
+        fs::write(dev.join("ttyS0"), b"").unwrap();
+        symlink(
+            dev.join("ttyUSB0"),
+            dev.join("serial/by-id/usb-FTDI_FT232R_AL065KM0-if00-port0"),
+        )
+        .unwrap();
+
+        let usb = sys.join("devices/usb1/1-1");
+        fs::create_dir_all(usb.join("1-1:1.0/ttyUSB0")).unwrap();
+        fs::write(usb.join("idVendor"), "0403\n").unwrap();
+        fs::write(usb.join("idProduct"), "6001\n").unwrap();
+        fs::write(usb.join("serial"), "AL065KM0\n").unwrap();
+        fs::create_dir_all(sys.join("class/ttyUSB0")).unwrap();
+        symlink(usb.join("1-1:1.0"), sys.join("class/ttyUSB0/device")).unwrap();
+
+        let ports = scan(&dev, &sys.join("class")).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(ports.len(), 2);
+        assert!(ports[0].path.ends_with("ttyUSB0"));
+        assert!(ports[0].by_id.is_some());
+        let usb = ports[0].usb.as_ref().unwrap();
+        assert_eq!(usb.vendor_id, FTDI_VENDOR_ID);
+        assert_eq!(usb.serial.as_deref(), Some("AL065KM0"));
+        assert!(ports[1].path.ends_with("ttyACM0"));
+        assert_eq!(ports[1].usb, None);
+    }
+
+    #[test]
+    pub fn probe_emulator() {
+        let emulator = Emulator::new();
+        let mut transport = emulator.transport();
+        assert!(probe_transport(&mut transport, Duration::from_millis(200)).unwrap());
+
+        let (mut silent, _peer) = Loopback::pair();
+        assert!(!probe_transport(&mut silent, Duration::from_millis(50)).unwrap());
+    }
+}
@@ -0,0 +1,564 @@
+//! Software stand-in for an MTRF-64 adapter.
+//!
+//! The emulator consumes request frames, keeps a bind table per channel for
+//! every [`Mode`] and answers the way the adapter does. Simulated nooLite-F
+//! devices can be bound, switched and queried, and remote presses can be
+//! injected to produce unsolicited RX/RX-F messages.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+
+use crate::cmd::request::Request;
+use crate::cmd::response::Response;
+use crate::cmd::{
+    Cmd, CtrRequest, CtrResponse, Mode, SetBrightness, CHANNELS, CLEAR_MEMORY_KEY, MESSAGE_LENGTH,
+};
+use crate::transport::Transport;
+
+#[cfg(target_os = "linux")]
+pub mod pty;
+
+/// Simulated nooLite-F power unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimDevice {
+    pub id: u32,
+    pub device_type: u8,
+    pub firmware: u8,
+    /// The service button is pressed, so the device accepts a bind command.
+    pub service: bool,
+    /// Devices that are offline never answer, which the adapter reports as `NoResponse`.
+    pub online: bool,
+    pub on: bool,
+    pub brightness: u8,
+}
+
+impl SimDevice {
+    pub fn new(id: u32) -> SimDevice {
+        SimDevice {
+            id,
+            device_type: 5,
+            firmware: 1,
+            service: true,
+            online: true,
+            on: false,
+            brightness: 255,
+        }
+    }
+
+    fn apply(&mut self, cmd: &Cmd) {
+        match *cmd {
+            Cmd::Off => self.on = false,
+            Cmd::On => self.on = true,
+            Cmd::Switch => self.on = !self.on,
+            Cmd::SetBrightness(SetBrightness::Fmt1(level)) => {
+                self.brightness = level;
+                self.on = level > 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    binds: [Vec<Vec<u32>>; 4],
+    devices: Vec<SimDevice>,
+    bind_mode: Option<(Mode, u8)>,
+    outbox: VecDeque<u8>,
+    unplugged: bool,
+    /// Bumped on every unplug, transports opened before it stay broken.
+    plug_count: u64,
+}
+
+impl State {
+    fn table(&mut self, mode: Mode) -> Option<&mut Vec<Vec<u32>>> {
+        match mode {
+            Mode::TX | Mode::RX | Mode::TxF | Mode::RxF => {
+                let table = &mut self.binds[mode as usize];
+                if table.is_empty() {
+                    table.resize(CHANNELS as usize, vec![]);
+                }
+                Some(table)
+            }
+            Mode::Service | Mode::FirmwareUpdate => None,
+        }
+    }
+
+    fn push(&mut self, resp: Response) {
+        self.outbox.extend(resp.to_message().iter());
+    }
+
+    fn device(&mut self, id: u32) -> Option<&mut SimDevice> {
+        self.devices.iter_mut().find(|dev| dev.id == id)
+    }
+}
+
+struct Inner {
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+/// Handle to an emulated adapter. Clones share the same adapter state.
+#[derive(Clone)]
+pub struct Emulator {
+    inner: Arc<Inner>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Emulator::new()
+    }
+}
+
+impl Emulator {
+    pub fn new() -> Emulator {
+        Emulator {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State::default()),
+                ready: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Transport that talks to this emulator as if it were a serial port.
+    pub fn transport(&self) -> EmulatorTransport {
+        EmulatorTransport {
+            emulator: self.clone(),
+            timeout: Duration::from_millis(20),
+            plug_count: self.inner.state.lock().unwrap().plug_count,
+        }
+    }
+
+    /// Like [`Emulator::transport`], but fails while the adapter is unplugged.
+    pub fn connect(&self) -> Result<EmulatorTransport, Error> {
+        ensure!(!self.is_unplugged(), "Adapter is unplugged");
+        Ok(self.transport())
+    }
+
+    /// Simulates pulling the USB cable. Open transports fail from now on and
+    /// [`Emulator::connect`] is refused until [`Emulator::plug`]. The bind
+    /// table survives, as it lives in the adapter's flash memory.
+    pub fn unplug(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.unplugged = true;
+        state.plug_count += 1;
+        state.outbox.clear();
+        state.bind_mode = None;
+        self.inner.ready.notify_all();
+    }
+
+    pub fn plug(&self) {
+        self.inner.state.lock().unwrap().unplugged = false;
+    }
+
+    pub fn is_unplugged(&self) -> bool {
+        self.inner.state.lock().unwrap().unplugged
+    }
+
+    fn check_plugged(&self, plug_count: u64) -> io::Result<()> {
+        let state = self.inner.state.lock().unwrap();
+        if state.unplugged || state.plug_count != plug_count {
+            Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Adapter unplugged",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn add_device(&self, device: SimDevice) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.devices.retain(|dev| dev.id != device.id);
+        state.devices.push(device);
+    }
+
+    pub fn device(&self, id: u32) -> Option<SimDevice> {
+        self.inner.state.lock().unwrap().device(id).copied()
+    }
+
+    pub fn update_device<F: FnOnce(&mut SimDevice)>(&self, id: u32, f: F) -> bool {
+        match self.inner.state.lock().unwrap().device(id) {
+            Some(dev) => {
+                f(dev);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Addresses bound to the channel. nooLite TX channels report `0` for every bind.
+    pub fn bound(&self, mode: Mode, ch: u8) -> Vec<u32> {
+        let mut state = self.inner.state.lock().unwrap();
+        state
+            .table(mode)
+            .and_then(|table| table.get(ch as usize).cloned())
+            .unwrap_or_default()
+    }
+
+    pub fn bind_mode(&self) -> Option<(Mode, u8)> {
+        self.inner.state.lock().unwrap().bind_mode
+    }
+
+    /// Simulates a remote control with the given address sending `cmd`.
+    ///
+    /// Returns `false` if the adapter ignored the press because the remote
+    /// is not bound and the adapter is not waiting for a bind on that mode.
+    pub fn press(&self, mode: Mode, remote: u32, cmd: Cmd) -> bool {
+        let (fmt, data) = cmd.payload();
+        self.press_raw(mode, remote, cmd.as_u8(), fmt, data)
+    }
+
+    /// Same as [`Emulator::press`] with an arbitrary command byte and payload,
+    /// e.g. a `SensTempHumi` reading from a sensor.
+    pub fn press_raw(&self, mode: Mode, remote: u32, cmd: u8, fmt: u8, data: [u8; 4]) -> bool {
+        if mode != Mode::RX && mode != Mode::RxF {
+            return false;
+        }
+        let cmd = match Cmd::try_from(&[cmd, fmt, data[0], data[1], data[2], data[3]][..]) {
+            Ok(cmd) => cmd,
+            Err(_) => return false,
+        };
+        let mut state = self.inner.state.lock().unwrap();
+        let (ctr, ch) = match state.bind_mode {
+            Some((bind_mode, ch)) if bind_mode == mode && cmd == Cmd::Bind => {
+                state.bind_mode = None;
+                let table = state.table(mode).unwrap();
+                if !table[ch as usize].contains(&remote) {
+                    table[ch as usize].push(remote);
+                }
+                (CtrResponse::BindSuccess, ch)
+            }
+            _ => {
+                let table = state.table(mode).unwrap();
+                match table.iter().position(|ids| ids.contains(&remote)) {
+                    Some(ch) => (CtrResponse::Success, ch as u8),
+                    None => return false,
+                }
+            }
+        };
+        state.push(Response {
+            mode,
+            ctr,
+            togl: 0,
+            ch,
+            cmd,
+            fmt,
+            data,
+            id: remote,
+            crc: 0,
+        });
+        self.inner.ready.notify_all();
+        true
+    }
+
+    /// Processes one request frame and queues the adapter's answers.
+    ///
+    /// A channel out of range is answered with `Error`, a frame that does
+    /// not decode is returned as an error and nothing is queued.
+    pub fn handle(&self, msg: &[u8; MESSAGE_LENGTH]) -> Result<(), Error> {
+        let req = Request::try_from(*msg)?;
+
+        let mut state = self.inner.state.lock().unwrap();
+        let (mode, ch) = (req.mode, req.ch as usize);
+        match (mode, req.ctr) {
+            _ if req.ch >= CHANNELS => state.push(answer(&req, CtrResponse::Error)),
+            (Mode::Service, _) | (Mode::FirmwareUpdate, _) => {
+                state.push(answer(&req, CtrResponse::Success))
+            }
+            (Mode::RX, CtrRequest::BindModeOn) | (Mode::RxF, CtrRequest::BindModeOn) => {
+                // The answer arrives once a remote sends its bind command.
+                state.bind_mode = Some((mode, req.ch));
+            }
+            (_, CtrRequest::BindModeOff) => {
+                state.bind_mode = None;
+                state.push(answer(&req, CtrResponse::Success));
+            }
+            (_, CtrRequest::ClearChannel) => {
+                state.table(mode).unwrap()[ch].clear();
+                state.push(answer(&req, CtrResponse::Success));
+            }
+            (_, CtrRequest::ClearMemory) => {
+                if req.cmd.payload().1 == CLEAR_MEMORY_KEY {
+                    for ids in state.table(mode).unwrap().iter_mut() {
+                        ids.clear();
+                    }
+                    state.push(answer(&req, CtrResponse::Success));
+                } else {
+                    state.push(answer(&req, CtrResponse::Error));
+                }
+            }
+            (_, CtrRequest::UnbindAddressFromChannel) => {
+                state.table(mode).unwrap()[ch].retain(|bound| *bound != req.id);
+                state.push(answer(&req, CtrResponse::Success));
+            }
+            (Mode::TxF, _) => self.handle_txf(&mut state, &req),
+            (Mode::TX, _) => {
+                if req.cmd == Cmd::Bind {
+                    let ids = &mut state.table(mode).unwrap()[ch];
+                    if ids.is_empty() {
+                        ids.push(0);
+                    }
+                } else if req.cmd == Cmd::Unbind {
+                    state.table(mode).unwrap()[ch].clear();
+                }
+                // nooLite transmitters get no feedback, so the adapter only confirms sending.
+                state.push(answer(&req, CtrResponse::Success));
+            }
+            (Mode::RX, _) | (Mode::RxF, _) => state.push(answer(&req, CtrResponse::Success)),
+        }
+        drop(state);
+        self.inner.ready.notify_all();
+        Ok(())
+    }
+
+    fn handle_txf(&self, state: &mut State, req: &Request) {
+        let ch = req.ch as usize;
+        if req.cmd == Cmd::Bind {
+            let candidate = state
+                .devices
+                .iter()
+                .find(|dev| dev.service && dev.online)
+                .map(|dev| dev.id);
+            match candidate {
+                Some(dev_id) => {
+                    let ids = &mut state.table(Mode::TxF).unwrap()[ch];
+                    if !ids.contains(&dev_id) {
+                        ids.push(dev_id);
+                    }
+                    state.device(dev_id).unwrap().service = false;
+                    state.push(Response {
+                        id: dev_id,
+                        ..answer(req, CtrResponse::Success)
+                    });
+                }
+                None => state.push(answer(req, CtrResponse::NoResponse)),
+            }
+            return;
+        }
+
+        let bound = state.table(Mode::TxF).unwrap()[ch].clone();
+        let targets: Vec<u32> = match req.ctr {
+            CtrRequest::SendCommandToId => vec![req.id],
+            CtrRequest::SendCommandToIdInChannel => {
+                bound.into_iter().filter(|bound| *bound == req.id).collect()
+            }
+            _ => bound,
+        };
+
+        let mut answers = vec![];
+        for target in targets {
+            let resp = match state.device(target) {
+                Some(dev) if dev.online => {
+                    dev.apply(&req.cmd);
+                    let resp = Response {
+                        id: target,
+                        ..answer(req, CtrResponse::Success)
+                    };
+                    if req.cmd == Cmd::ReadState {
+                        Response {
+                            cmd: Cmd::SendState,
+                            fmt: 0,
+                            data: [dev.device_type, dev.firmware, dev.on as u8, dev.brightness],
+                            ..resp
+                        }
+                    } else {
+                        resp
+                    }
+                }
+                // A bound device out of reach still gets its place in the sequence.
+                Some(_) => Response {
+                    id: target,
+                    ..answer(req, CtrResponse::NoResponse)
+                },
+                None => continue,
+            };
+            if resp.ctr == CtrResponse::Success && req.cmd == Cmd::Unbind {
+                for ids in state.table(Mode::TxF).unwrap().iter_mut() {
+                    ids.retain(|bound| *bound != target);
+                }
+            }
+            answers.push(resp);
+        }
+
+        if req.ctr == CtrRequest::SendBroadcastCommand {
+            // Broadcasts are not acknowledged by the devices.
+            state.push(answer(req, CtrResponse::Success));
+        } else if answers.is_empty() {
+            state.push(answer(req, CtrResponse::NoResponse));
+        } else {
+            let count = answers.len();
+            for (idx, resp) in answers.into_iter().enumerate() {
+                let togl = (count - idx - 1) as u8;
+                state.push(Response { togl, ..resp });
+            }
+        }
+    }
+
+    /// Pops queued answer bytes, waiting up to `timeout` for some to appear.
+    fn read(&self, buf: &mut [u8], timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.inner.state.lock().unwrap();
+        loop {
+            if !state.outbox.is_empty() {
+                let count = buf.len().min(state.outbox.len());
+                for (dst, src) in buf.iter_mut().zip(state.outbox.drain(..count)) {
+                    *dst = src;
+                }
+                return count;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return 0;
+            }
+            state = self
+                .inner
+                .ready
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+/// Answer to `req` that echoes its channel, command and address.
+fn answer(req: &Request, ctr: CtrResponse) -> Response {
+    let (fmt, data) = req.cmd.payload();
+    Response {
+        mode: req.mode,
+        ctr,
+        togl: 0,
+        ch: req.ch,
+        cmd: req.cmd,
+        fmt,
+        data,
+        id: req.id,
+        crc: 0,
+    }
+}
+
+/// [`Transport`] backed by an [`Emulator`].
+pub struct EmulatorTransport {
+    emulator: Emulator,
+    timeout: Duration,
+    plug_count: u64,
+}
+
+impl Transport for EmulatorTransport {
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.emulator.check_plugged(self.plug_count)?;
+        Ok(self.emulator.read(buf, self.timeout))
+    }
+
+    /// Only an unplugged emulator fails the write, a frame it cannot decode
+    /// is logged and dropped.
+    fn write_frame(&mut self, frame: &[u8; MESSAGE_LENGTH]) -> io::Result<()> {
+        self.emulator.check_plugged(self.plug_count)?;
+        if let Err(err) = self.emulator.handle(frame) {
+            warn!("Emulator rejected request: {}", err);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::channel;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::clear::{clear, Wipe};
+    use crate::cmd::request::{bind, Request};
+    use crate::cmd::{Cmd, CtrResponse, Mode};
+    use crate::emulator::{Emulator, SimDevice};
+    use crate::error::RequestError;
+    use crate::mtrf::Mtrf;
+    use crate::registry::Registry;
+
+    #[test]
+    pub fn txf_bind_and_switch() {
+        let emulator = Emulator::new();
+        emulator.add_device(SimDevice::new(0x1234));
+        let (tx, _rx) = channel();
+        let mtrf = Mtrf::with_transport(emulator.transport(), tx).unwrap();
+
+        let resp = mtrf.send_request(bind(Mode::TxF, 3)).unwrap();
+        assert_eq!(resp.ctr, CtrResponse::Success);
+        assert_eq!(resp.id, 0x1234);
+        assert_eq!(emulator.bound(Mode::TxF, 3), vec![0x1234]);
+
+        let on = Request {
+            mode: Mode::TxF,
+            ch: 3,
+            cmd: Cmd::On,
+            ..Default::default()
+        };
+        let resp = mtrf.send_request(on).unwrap();
+        assert_eq!(resp.ctr, CtrResponse::Success);
+        assert!(emulator.device(0x1234).unwrap().on);
+
+        emulator.update_device(0x1234, |dev| dev.online = false);
+        match mtrf.send_request(on) {
+            Err(RequestError::DeviceNoResponse(resp)) => assert_eq!(resp.ch, 3),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let out_of_range = Request { ch: 64, ..on };
+        match mtrf.send_request(out_of_range) {
+            Err(RequestError::AdapterError(resp)) => assert_eq!(resp.ch, 64),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let wipe = Wipe::Channel {
+            mode: Mode::TxF,
+            ch: 3,
+        };
+        let confirmation = wipe.confirm("clear channel 3 of TxF").unwrap();
+        let registry = Mutex::new(Registry::new());
+        clear(
+            &mtrf,
+            &registry,
+            confirmation,
+            Duration::from_secs(1),
+            |_| Ok(()),
+        )
+        .unwrap();
+        assert!(emulator.bound(Mode::TxF, 3).is_empty());
+    }
+
+    #[test]
+    pub fn rxf_bind_and_press() {
+        let emulator = Emulator::new();
+        let (tx, rx) = channel();
+        let mtrf = Mtrf::with_transport(emulator.transport(), tx).unwrap();
+
+        let remote = emulator.clone();
+        let presser = thread::spawn(move || {
+            while remote.bind_mode().is_none() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            assert!(remote.press(Mode::RxF, 77, Cmd::Bind));
+        });
+        let resp = mtrf.send_request(bind(Mode::RxF, 1)).unwrap();
+        presser.join().unwrap();
+        assert_eq!(resp.ctr, CtrResponse::BindSuccess);
+        assert_eq!(resp.id, 77);
+
+        assert!(emulator.press(Mode::RxF, 77, Cmd::Switch));
+        assert!(!emulator.press(Mode::RxF, 78, Cmd::Switch));
+        let msg = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(msg.ch, 1);
+        assert_eq!(msg.mode, Mode::RxF);
+        assert_eq!(msg.cmd.as_u8(), Cmd::Switch.as_u8());
+    }
+}
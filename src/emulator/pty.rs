@@ -0,0 +1,187 @@
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::cmd::decoder::FrameDecoder;
+use crate::cmd::{MESSAGE_LENGTH, REQUEST_SP, REQUEST_ST};
+use crate::emulator::Emulator;
+
+/// Emulator exposed on a Linux pseudo-terminal, so it can be opened by path
+/// like a real adapter.
+pub struct Pty {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+    // Keeps the slave side open, otherwise the master reports EIO whenever
+    // no client is connected.
+    _slave: File,
+}
+
+impl Pty {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Emulator {
+    /// Serves the emulator on a new pseudo-terminal until the returned [`Pty`] is dropped.
+    pub fn attach_pty(&self) -> Result<Pty, Error> {
+        let (master, path) = open_master()?;
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+        make_raw(&slave)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut reader = master.try_clone()?;
+        let mut writer = master;
+
+        let emulator = self.clone();
+        let reader_stop = stop.clone();
+        let read_worker = thread::spawn(move || {
+            let mut decoder = FrameDecoder::with_markers(REQUEST_ST, REQUEST_SP);
+            let mut buf = [0; MESSAGE_LENGTH];
+            while !reader_stop.load(Ordering::SeqCst) {
+                if !wait_readable(&reader, 20) {
+                    continue;
+                }
+                let count = match reader.read(&mut buf) {
+                    Ok(count) => count,
+                    Err(err) => {
+                        debug!("Emulator pty read failed: {}", err);
+                        thread::sleep(Duration::from_millis(20));
+                        continue;
+                    }
+                };
+                decoder.push(&buf[..count]);
+                while let Some(frame) = decoder.next_frame() {
+                    if let Err(err) = emulator.handle(&frame) {
+                        warn!("Emulator rejected request: {}", err);
+                    }
+                }
+            }
+        });
+
+        let emulator = self.clone();
+        let writer_stop = stop.clone();
+        let write_worker = thread::spawn(move || {
+            let mut buf = [0; MESSAGE_LENGTH];
+            while !writer_stop.load(Ordering::SeqCst) {
+                let count = emulator.read(&mut buf, Duration::from_millis(20));
+                if count > 0 {
+                    if let Err(err) = writer.write_all(&buf[..count]) {
+                        warn!("Emulator pty write failed: {}", err);
+                    }
+                }
+            }
+        });
+
+        Ok(Pty {
+            path,
+            stop,
+            workers: vec![read_worker, write_worker],
+            _slave: slave,
+        })
+    }
+}
+
+fn open_master() -> Result<(File, PathBuf), Error> {
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        ensure!(
+            fd >= 0,
+            "posix_openpt failed: {}",
+            std::io::Error::last_os_error()
+        );
+        let master = File::from_raw_fd(fd);
+        ensure!(
+            libc::grantpt(fd) == 0 && libc::unlockpt(fd) == 0,
+            "Failed to unlock pty: {}",
+            std::io::Error::last_os_error()
+        );
+
+        let mut name = [0 as libc::c_char; 128];
+        ensure!(
+            libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) == 0,
+            "ptsname failed: {}",
+            std::io::Error::last_os_error()
+        );
+        let path = PathBuf::from(CStr::from_ptr(name.as_ptr()).to_str()?);
+        Ok((master, path))
+    }
+}
+
+fn make_raw(file: &File) -> Result<(), Error> {
+    unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        ensure!(
+            libc::tcgetattr(file.as_raw_fd(), &mut termios) == 0,
+            "tcgetattr failed: {}",
+            std::io::Error::last_os_error()
+        );
+        libc::cfmakeraw(&mut termios);
+        ensure!(
+            libc::tcsetattr(file.as_raw_fd(), libc::TCSANOW, &termios) == 0,
+            "tcsetattr failed: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+fn wait_readable(file: &File, timeout_ms: i32) -> bool {
+    let mut fds = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, timeout_ms) > 0 && fds.revents & libc::POLLIN != 0 }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::request::Request;
+    use crate::cmd::{Cmd, CtrResponse, Mode};
+    use crate::emulator::{Emulator, SimDevice};
+    use crate::mtrf::tests::Logger;
+    use crate::mtrf::Mtrf;
+
+    #[test]
+    pub fn serial_over_pty() {
+        let emulator = Emulator::new();
+        emulator.add_device(SimDevice::new(9));
+        let pty = emulator.attach_pty().unwrap();
+
+        let mtrf = Mtrf::new(pty.path().to_str().unwrap(), Logger).unwrap();
+        let resp = mtrf
+            .send_request(Request {
+                mode: Mode::TxF,
+                cmd: Cmd::Bind,
+                ch: 2,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(resp.ctr, CtrResponse::Success);
+        assert_eq!(emulator.bound(Mode::TxF, 2), vec![9]);
+    }
+}
@@ -0,0 +1,42 @@
+use std::error;
+use std::fmt;
+
+use crate::cmd::response::Response;
+
+/// Failure of a request sent through [`Mtrf`](crate::mtrf::Mtrf).
+#[derive(Debug, Clone, Copy)]
+pub enum RequestError {
+    /// The adapter did not answer on the awaited channel in time.
+    Timeout,
+    /// The worker thread is gone, usually because the port failed.
+    TransportClosed,
+    /// The port is down and a supervised worker is reconnecting.
+    Disconnected,
+    /// The adapter reached no device (`CtrResponse::NoResponse`).
+    DeviceNoResponse(Response),
+    /// The adapter rejected the request (`CtrResponse::Error`).
+    AdapterError(Response),
+    /// The answer does not carry what the request asked for.
+    UnexpectedResponse(Response),
+    /// The request was rejected before being sent.
+    InvalidRequest(&'static str),
+    /// The operation was cancelled by the caller.
+    Cancelled,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Timeout => write!(f, "Timed out waiting for the adapter response"),
+            RequestError::TransportClosed => write!(f, "Transport closed"),
+            RequestError::Disconnected => write!(f, "Adapter disconnected"),
+            RequestError::DeviceNoResponse(resp) => write!(f, "Device did not respond: {}", resp),
+            RequestError::AdapterError(resp) => write!(f, "Adapter error: {}", resp),
+            RequestError::UnexpectedResponse(resp) => write!(f, "Unexpected response: {}", resp),
+            RequestError::InvalidRequest(reason) => write!(f, "Invalid request: {}", reason),
+            RequestError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl error::Error for RequestError {}
@@ -0,0 +1,164 @@
+//! Ready-made [`OnMessage`] handlers: closures, channels and the adapters
+//! returned by [`OnMessage::filter`], [`OnMessage::map`] and [`OnMessage::tee`].
+
+use std::sync::mpsc::Sender;
+
+use crate::cmd::response::Response;
+use crate::mtrf::OnMessage;
+
+/// Any `FnMut(Response)` closure, e.g. `Mtrf::new(port, |msg: Response| println!("{}", msg))`.
+impl<F: FnMut(Response)> OnMessage for F {
+    fn on_message(&mut self, msg: Response) {
+        self(msg)
+    }
+}
+
+/// Forwards to the receiving end, messages are dropped once it is gone.
+impl OnMessage for Sender<Response> {
+    fn on_message(&mut self, msg: Response) {
+        let _ = self.send(msg);
+    }
+}
+
+/// Every handler gets each message, in order.
+impl<H: OnMessage> OnMessage for Vec<H> {
+    fn on_message(&mut self, msg: Response) {
+        for handler in self.iter_mut() {
+            handler.on_message(msg);
+        }
+    }
+}
+
+/// See [`OnMessage::filter`].
+pub struct Filtered<H, P> {
+    handler: H,
+    predicate: P,
+}
+
+impl<H, P> Filtered<H, P> {
+    pub(crate) fn new(handler: H, predicate: P) -> Self {
+        Filtered { handler, predicate }
+    }
+}
+
+impl<H: OnMessage, P: FnMut(&Response) -> bool> OnMessage for Filtered<H, P> {
+    fn on_message(&mut self, msg: Response) {
+        if (self.predicate)(&msg) {
+            self.handler.on_message(msg);
+        }
+    }
+}
+
+/// See [`OnMessage::map`].
+pub struct Mapped<H, F> {
+    handler: H,
+    f: F,
+}
+
+impl<H, F> Mapped<H, F> {
+    pub(crate) fn new(handler: H, f: F) -> Self {
+        Mapped { handler, f }
+    }
+}
+
+impl<H: OnMessage, F: FnMut(Response) -> Response> OnMessage for Mapped<H, F> {
+    fn on_message(&mut self, msg: Response) {
+        self.handler.on_message((self.f)(msg));
+    }
+}
+
+/// See [`OnMessage::tee`].
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> Tee<A, B> {
+    pub(crate) fn new(first: A, second: B) -> Self {
+        Tee { first, second }
+    }
+}
+
+impl<A: OnMessage, B: OnMessage> OnMessage for Tee<A, B> {
+    fn on_message(&mut self, msg: Response) {
+        self.first.on_message(msg);
+        self.second.on_message(msg);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::bus::Filter;
+    use crate::cmd::request::bind;
+    use crate::cmd::response::Response;
+    use crate::cmd::{Cmd, CtrResponse, Mode};
+    use crate::emulator::Emulator;
+    use crate::mtrf::{Mtrf, OnMessage};
+
+    fn message(ch: u8, cmd: Cmd) -> Response {
+        Response {
+            mode: Mode::RX,
+            ctr: CtrResponse::Success,
+            togl: 0,
+            ch,
+            cmd,
+            fmt: 0,
+            data: [0; 4],
+            id: 0,
+            crc: 0,
+        }
+    }
+
+    #[test]
+    pub fn adapters() {
+        let (all_tx, all) = channel();
+        let (odd_tx, odd) = channel();
+        let mut count = 0;
+        {
+            let filter = Filter::any().cmd(Cmd::Switch);
+            let mut handler = all_tx
+                .tee(odd_tx.filter(|msg: &Response| msg.ch % 2 == 1))
+                .tee(|_: Response| count += 1)
+                .map(|mut msg: Response| {
+                    msg.ch += 1;
+                    msg
+                })
+                .filter(move |msg: &Response| filter.matches(msg));
+            handler.on_message(message(0, Cmd::Switch));
+            handler.on_message(message(1, Cmd::Switch));
+            handler.on_message(message(2, Cmd::On));
+        }
+        assert_eq!(count, 2);
+        assert_eq!(all.iter().map(|msg| msg.ch).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(odd.iter().map(|msg| msg.ch).collect::<Vec<_>>(), vec![1]);
+
+        let (tx, rx) = channel();
+        let mut handlers = vec![tx.clone(), tx];
+        handlers.on_message(message(5, Cmd::On));
+        drop(handlers);
+        assert_eq!(rx.iter().count(), 2);
+    }
+
+    #[test]
+    pub fn closure_handler() {
+        let emulator = Emulator::new();
+        let (tx, rx) = channel();
+        let mtrf = Mtrf::with_transport(emulator.transport(), move |msg: Response| {
+            tx.send(msg).unwrap()
+        })
+        .unwrap();
+
+        // Without a request waiting for it, the bind answer is unsolicited.
+        mtrf.send(bind(Mode::RX, 1)).unwrap();
+        while emulator.bind_mode().is_none() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(emulator.press(Mode::RX, 7, Cmd::Bind));
+        let msg = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((msg.ch, msg.ctr), (1, CtrResponse::BindSuccess));
+    }
+}
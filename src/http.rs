@@ -0,0 +1,431 @@
+//! REST API over one shared adapter.
+//!
+//! - `GET /channels/{mode}/{ch}/state`: states of the devices of a TX-F channel.
+//! - `POST /channels/{mode}/{ch}/command`: sends a command, e.g. `{"cmd": "on"}`,
+//!   `{"cmd": "set_brightness", "level": 128}`,
+//!   `{"cmd": "set_brightness", "color": {"r": 255, "g": 0, "b": 0}}` or
+//!   `{"cmd": "temporary_on", "seconds": 60}`. `"id"` addresses one nooLite-F
+//!   device and `"broadcast": true` sends to the whole channel.
+//! - `POST /bind`: `{"mode": "txf", "ch": 3, "name": "hall.lamp", "kind": "relay", "timeout": 30}`,
+//!   on the first free channel if `ch` is omitted.
+//! - `DELETE /channels/{mode}/{ch}`: clears the channel, the body must be
+//!   `{"confirm": "clear channel 3 of TxF"}`.
+//! - `GET /events`: Server-Sent Events with every message of the adapter.
+//!
+//! Failures are answered with `{"error": "..."}`.
+
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Error;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Server};
+
+use crate::bind::BindSession;
+use crate::bus::{EventBus, Filter};
+use crate::clear::{clear, Wipe};
+use crate::cmd::address::Address;
+use crate::cmd::payload::Payload;
+use crate::cmd::repr::CmdRepr;
+use crate::cmd::request::Request;
+use crate::cmd::response::Response;
+use crate::cmd::{Cmd, CtrRequest, Mode, CHANNELS};
+use crate::error::RequestError;
+use crate::mtrf::Mtrf;
+use crate::registry::{DeviceKind, Registry};
+
+/// Comment sent to idle event streams, so dead clients are noticed.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// Default time to wait for a device in `POST /bind`.
+const BIND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct NotFound;
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Not found")
+    }
+}
+
+impl error::Error for NotFound {}
+
+struct Inner {
+    mtrf: Mtrf,
+    registry: Mutex<Registry>,
+    /// File the registry is saved to after binds and wipes.
+    registry_path: Option<PathBuf>,
+    /// Held by a bind from picking its channel until it is recorded, so two
+    /// binds never take the same free channel.
+    binding: Mutex<()>,
+    events: EventBus,
+}
+
+/// Request handler. Clones share the adapter and the registry.
+#[derive(Clone)]
+pub struct Api {
+    inner: Arc<Inner>,
+}
+
+impl Api {
+    /// Opens the adapter with `open`, which gets the bus behind `GET /events`
+    /// as the handler for its unsolicited messages.
+    pub fn new<F>(registry: Registry, registry_path: Option<PathBuf>, open: F) -> Result<Api, Error>
+    where
+        F: FnOnce(EventBus) -> Result<Mtrf, Error>,
+    {
+        let events = EventBus::new();
+        let mtrf = open(events.clone())?;
+        Ok(Api {
+            inner: Arc::new(Inner {
+                mtrf,
+                registry: Mutex::new(registry),
+                registry_path,
+                binding: Mutex::new(()),
+                events,
+            }),
+        })
+    }
+
+    pub fn mtrf(&self) -> &Mtrf {
+        &self.inner.mtrf
+    }
+
+    /// Serves until the server is unblocked, every request on its own thread.
+    pub fn serve(&self, server: &Server) {
+        for request in server.incoming_requests() {
+            let api = self.clone();
+            thread::spawn(move || api.handle(request));
+        }
+    }
+
+    fn handle(&self, mut request: tiny_http::Request) {
+        let method = request.method().clone();
+        let url = request.url().to_owned();
+        if method == Method::Get && url == "/events" {
+            self.stream_events(request);
+            return;
+        }
+
+        let mut body = String::new();
+        let result = match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => self.route(&method, &url, &body),
+            Err(err) => Err(err.into()),
+        };
+        let (status, json) = match result {
+            Ok(json) => (200, json),
+            Err(err) => {
+                debug!("{} {} failed: {}", method, url, err);
+                (status_of(&err), json!({ "error": err.to_string() }))
+            }
+        };
+        let response = tiny_http::Response::from_string(json.to_string())
+            .with_status_code(status)
+            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap());
+        if let Err(err) = request.respond(response) {
+            debug!("Failed to answer {} {}: {}", method, url, err);
+        }
+    }
+
+    fn route(&self, method: &Method, url: &str, body: &str) -> Result<Value, Error> {
+        let path = url.split('?').next().unwrap_or_default().trim_matches('/');
+        let path: Vec<&str> = path.split('/').collect();
+        match (method, path.as_slice()) {
+            (Method::Get, ["channels", mode, ch, "state"]) => {
+                let (mode, ch) = parse_channel(mode, ch)?;
+                self.state(mode, ch)
+            }
+            (Method::Post, ["channels", mode, ch, "command"]) => {
+                let (mode, ch) = parse_channel(mode, ch)?;
+                self.command(mode, ch, &parse_body(body)?)
+            }
+            (Method::Post, ["bind"]) => self.bind(&parse_body(body)?),
+            (Method::Delete, ["channels", mode, ch]) => {
+                let (mode, ch) = parse_channel(mode, ch)?;
+                self.clear(mode, ch, &parse_body(body)?)
+            }
+            _ => Err(NotFound.into()),
+        }
+    }
+
+    fn state(&self, mode: Mode, ch: u8) -> Result<Value, Error> {
+        ensure!(
+            mode == Mode::TxF,
+            "Only nooLite-F devices report their state"
+        );
+        let req = Address::Channel(ch).request(mode, Cmd::ReadState)?;
+        let states = self
+            .send(req)?
+            .iter()
+            .filter_map(|resp| match resp.payload() {
+                Payload::State(state) => {
+                    let mut state = state.to_json();
+                    state["id"] = json!(resp.id);
+                    Some(state)
+                }
+                _ => None,
+            })
+            .collect();
+        Ok(Value::Array(states))
+    }
+
+    fn command(&self, mode: Mode, ch: u8, body: &Value) -> Result<Value, Error> {
+        let address = match (body.get("id"), body["broadcast"].as_bool()) {
+            (Some(id), _) => Address::IdInChannel(ch, number(id)?),
+            (None, Some(true)) => Address::Broadcast(ch),
+            (None, _) => Address::Channel(ch),
+        };
+        let req = address.request(mode, parse_command(body)?)?;
+        let answers = self.send(req)?;
+        Ok(answers.iter().map(Response::to_json).collect())
+    }
+
+    fn bind(&self, body: &Value) -> Result<Value, Error> {
+        let mode: Mode = body["mode"]
+            .as_str()
+            .ok_or_else(|| anyhow!("The mode is required"))?
+            .parse()?;
+        let kind: DeviceKind = match body.get("kind") {
+            Some(kind) => serde_json::from_value(kind.clone())?,
+            None => DeviceKind::Other,
+        };
+        let timeout = match body.get("timeout") {
+            Some(timeout) => Duration::from_secs(number(timeout)?),
+            None => BIND_TIMEOUT,
+        };
+        let mtrf = self.inner.mtrf.clone();
+        let _binding = self.inner.binding.lock().unwrap();
+        let session = match body.get("ch") {
+            Some(ch) => BindSession::new(mtrf, mode, number(ch)?)?,
+            None => BindSession::on_free_channel(mtrf, mode, &self.inner.registry.lock().unwrap())?,
+        };
+        let bound = session.run(timeout)?;
+        self.inner.events.publish(&bound.response);
+        if let Some(name) = body["name"].as_str() {
+            let mut registry = self.inner.registry.lock().unwrap();
+            registry.record_bind(&bound.request, &bound.response, name, kind)?;
+            self.save(&registry)?;
+        }
+        Ok(json!({ "mode": mode.name(), "ch": bound.ch, "id": bound.id }))
+    }
+
+    fn clear(&self, mode: Mode, ch: u8, body: &Value) -> Result<Value, Error> {
+        let wipe = Wipe::Channel { mode, ch };
+        let confirmation = wipe.confirm(body["confirm"].as_str().unwrap_or_default())?;
+        let timeout = self.inner.mtrf.default_timeout();
+        let backup = clear(
+            &self.inner.mtrf,
+            &self.inner.registry,
+            confirmation,
+            timeout,
+            |backup| {
+                if let Some(path) = &self.inner.registry_path {
+                    backup.save_beside(path)?;
+                }
+                Ok(())
+            },
+        )?;
+        self.save(&self.inner.registry.lock().unwrap())?;
+        let removed: Vec<String> = backup.entries.iter().map(|dev| dev.full_name()).collect();
+        Ok(json!({ "removed": removed }))
+    }
+
+    /// Sends the request, collecting every answer of a TX-F channel.
+    fn send(&self, req: Request) -> Result<Vec<Response>, RequestError> {
+        let answers = if req.mode == Mode::TxF && req.ctr == CtrRequest::SendCommand {
+            self.inner.mtrf.send_request_all(req)?
+        } else {
+            vec![self.inner.mtrf.send_request(req)?]
+        };
+        for resp in &answers {
+            self.inner.events.publish(resp);
+        }
+        Ok(answers)
+    }
+
+    fn save(&self, registry: &Registry) -> Result<(), Error> {
+        if let Some(path) = &self.inner.registry_path {
+            registry.save(path)?;
+        }
+        Ok(())
+    }
+
+    fn stream_events(&self, request: tiny_http::Request) {
+        let events = self.inner.events.subscribe(Filter::any());
+        let mut writer = request.into_writer();
+        let mut chunk = "HTTP/1.1 200 OK\r\n\
+                         Content-Type: text/event-stream\r\n\
+                         Cache-Control: no-cache\r\n\
+                         Connection: close\r\n\r\n"
+            .to_owned();
+        loop {
+            if writer
+                .write_all(chunk.as_bytes())
+                .and_then(|_| writer.flush())
+                .is_err()
+            {
+                debug!("Event stream closed");
+                return;
+            }
+            chunk = match events.recv_timeout(KEEP_ALIVE) {
+                Ok(resp) => format!("data: {}\n\n", resp.to_json()),
+                Err(RecvTimeoutError::Timeout) => ": keep-alive\n\n".to_owned(),
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+        }
+    }
+}
+
+fn status_of(err: &Error) -> u16 {
+    if err.downcast_ref::<NotFound>().is_some() {
+        return 404;
+    }
+    match err.downcast_ref::<RequestError>() {
+        Some(RequestError::Timeout) => 504,
+        Some(RequestError::TransportClosed) | Some(RequestError::Disconnected) => 503,
+        Some(RequestError::DeviceNoResponse(_))
+        | Some(RequestError::AdapterError(_))
+        | Some(RequestError::UnexpectedResponse(_)) => 502,
+        Some(RequestError::Cancelled) => 409,
+        Some(RequestError::InvalidRequest(_)) | None => 400,
+    }
+}
+
+fn parse_channel(mode: &str, ch: &str) -> Result<(Mode, u8), Error> {
+    let ch = ch.parse().map_err(|_| anyhow!("Invalid channel:{}", ch))?;
+    ensure!(ch < CHANNELS, "Invalid channel:{}", ch);
+    Ok((mode.parse()?, ch))
+}
+
+/// An empty body is an empty object.
+fn parse_body(body: &str) -> Result<Value, Error> {
+    if body.trim().is_empty() {
+        return Ok(json!({}));
+    }
+    let body: Value = serde_json::from_str(body)?;
+    ensure!(body.is_object(), "Expected a JSON object");
+    Ok(body)
+}
+
+fn number<T: TryFrom<u64>>(value: &Value) -> Result<T, Error> {
+    value
+        .as_u64()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| anyhow!("Invalid number: {}", value))
+}
+
+/// A command in the form of [`CmdRepr`], other fields of the body are ignored.
+fn parse_command(body: &Value) -> Result<Cmd, Error> {
+    let repr: CmdRepr = serde_json::from_value(body.clone())?;
+    Cmd::try_from(repr)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::Arc;
+    use std::{env, fs, process, thread};
+
+    use serde_json::{json, Value};
+    use tiny_http::Server;
+
+    use crate::emulator::{Emulator, SimDevice};
+    use crate::http::Api;
+    use crate::mtrf::Mtrf;
+    use crate::registry::Registry;
+
+    fn call(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    pub fn rest_api() {
+        let emulator = Emulator::new();
+        emulator.add_device(SimDevice::new(0x10));
+        let path = env::temp_dir().join(format!("mtrf-http-{}.toml", process::id()));
+        let api = Api::new(Registry::new(), Some(path.clone()), |sink| {
+            Mtrf::with_transport(emulator.transport(), sink)
+        })
+        .unwrap();
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let addr = server.server_addr().to_ip().unwrap();
+        let listener = server.clone();
+        thread::spawn(move || api.serve(&listener));
+
+        let (status, bound) = call(
+            addr,
+            "POST",
+            "/bind",
+            r#"{"mode": "txf", "name": "hall.lamp", "kind": "relay", "timeout": 2}"#,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(bound, json!({ "mode": "txf", "ch": 0, "id": 0x10 }));
+
+        let (status, answers) = call(addr, "POST", "/channels/txf/0/command", r#"{"cmd": "on"}"#);
+        assert_eq!(status, 200);
+        assert_eq!(answers[0]["id"], json!(0x10));
+        assert!(emulator.device(0x10).unwrap().on);
+        let (_, states) = call(addr, "GET", "/channels/txf/0/state", "");
+        assert_eq!(states[0]["state"], json!("ON"));
+        assert_eq!(states[0]["id"], json!(0x10));
+
+        let mut events = TcpStream::connect(addr).unwrap();
+        write!(events, "GET /events HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        let mut events = BufReader::new(events);
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            events.read_line(&mut line).unwrap();
+        }
+        let body = r#"{"cmd": "set_brightness", "level": 99}"#;
+        call(addr, "POST", "/channels/txf/0/command", body);
+        line.clear();
+        events.read_line(&mut line).unwrap();
+        let event: Value = serde_json::from_str(line.trim_start_matches("data: ")).unwrap();
+        assert_eq!(event["level"], json!(99));
+
+        let (status, error) = call(addr, "DELETE", "/channels/txf/0", "");
+        assert_eq!(status, 400);
+        assert!(error["error"]
+            .as_str()
+            .unwrap()
+            .contains("clear channel 0 of TxF"));
+        let confirm = r#"{"confirm": "clear channel 0 of TxF"}"#;
+        let (status, cleared) = call(addr, "DELETE", "/channels/txf/0", confirm);
+        assert_eq!(status, 200);
+        assert_eq!(cleared, json!({ "removed": ["hall.lamp"] }));
+        let backup = Registry::load(path.with_extension("bak.json")).unwrap();
+        assert_eq!(backup.devices()[0].full_name(), "hall.lamp");
+        assert!(Registry::load(&path).unwrap().devices().is_empty());
+        fs::remove_file(path.with_extension("bak.json")).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let (status, _) = call(addr, "POST", "/channels/txf/0/command", r#"{"cmd": "on"}"#);
+        assert_eq!(status, 502);
+        assert_eq!(call(addr, "POST", "/channels/txf/64/command", "").0, 400);
+        assert_eq!(call(addr, "GET", "/nothing", "").0, 404);
+        server.unblock();
+    }
+}
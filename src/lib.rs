@@ -3,5 +3,25 @@ extern crate anyhow;
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "async")]
+pub mod aio;
+pub mod bind;
+pub mod bus;
+pub mod clear;
 pub mod cmd;
+pub mod device;
+pub mod discovery;
+pub mod emulator;
+pub mod error;
+pub mod handler;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 pub mod mtrf;
+mod pending;
+pub mod registry;
+pub mod supervisor;
+pub mod transport;
+
+pub use discovery::ports;
@@ -1,19 +1,496 @@
-use mtrf::cmd::request::Request;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure, Error};
+use clap::{Args, Parser, Subcommand};
+use serde_json::{json, Value};
+
+use mtrf::bind::BindSession;
+use mtrf::bus::Filter;
+use mtrf::clear::{clear, Wipe};
+use mtrf::cmd::address::Address;
+use mtrf::cmd::request::{set_mode, Request};
 use mtrf::cmd::response::Response;
+use mtrf::cmd::{Cmd, CtrRequest, Mode, SetBrightness};
+use mtrf::device::brightness_from_percent;
+use mtrf::discovery::{find_adapters, list_ports};
+#[cfg(feature = "http")]
+use mtrf::http::Api;
+#[cfg(feature = "mqtt")]
+use mtrf::mqtt::{self, homeassistant::Discovery, Bridge, Topics};
 use mtrf::mtrf::{Mtrf, OnMessage};
-use std::thread;
+use mtrf::registry::{DeviceKind, Registry};
+
+/// Time to wait for an adapter to answer the probe during auto-detection.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Parser)]
+#[command(
+    name = "mtrf",
+    version,
+    about = "Control nooLite devices through an MTRF-64 adapter"
+)]
+struct Cli {
+    /// Serial port of the adapter. Detected automatically if omitted.
+    #[arg(long, global = true)]
+    port: Option<String>,
+    /// Print machine-readable JSON, one document per line.
+    #[arg(long, global = true)]
+    json: bool,
+    /// Bind-table registry file (.toml or .json).
+    #[arg(long, global = true)]
+    registry: Option<PathBuf>,
+    /// Seconds to wait for the adapter to answer.
+    #[arg(long, global = true, default_value_t = 5)]
+    timeout: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List serial ports that may hold an adapter.
+    Ports,
+    /// Print every message the adapter receives.
+    Listen {
+        #[arg(long, default_value = "rxf")]
+        mode: Mode,
+    },
+    /// Send a command: on, off, switch, brightness <0-100>, color <r> <g> <b>, ...
+    Send {
+        #[command(flatten)]
+        target: Target,
+        #[arg(required = true, num_args = 1..)]
+        cmd: Vec<String>,
+    },
+    /// Bind a device or remote, on the first free channel unless `--ch` is given.
+    Bind {
+        #[arg(long)]
+        mode: Mode,
+        #[arg(long)]
+        ch: Option<u8>,
+        /// Registry name of the device, `room.name`.
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long, default_value = "other")]
+        kind: Kind,
+        /// Seconds to wait for the device.
+        #[arg(long, default_value_t = 30)]
+        wait: u64,
+    },
+    /// Unbind a device from a channel.
+    Unbind {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Read the state of a nooLite-F device.
+    State {
+        #[arg(long, conflicts_with = "id", required_unless_present = "id")]
+        ch: Option<u8>,
+        #[arg(long, value_parser = parse_id)]
+        id: Option<u32>,
+    },
+    /// Unbind everything from a channel.
+    ClearChannel {
+        #[arg(long)]
+        mode: Mode,
+        #[arg(long)]
+        ch: u8,
+        /// The phrase `clear channel <ch> of <mode>`, as printed without it.
+        #[arg(long)]
+        confirm: Option<String>,
+    },
+    /// Serve the REST API.
+    #[cfg(feature = "http")]
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Bridge the adapter to an MQTT broker.
+    #[cfg(feature = "mqtt")]
+    Mqtt {
+        /// Host name of the broker.
+        #[arg(long, default_value = "localhost")]
+        broker: String,
+        #[arg(long, default_value_t = 1883)]
+        broker_port: u16,
+        /// Prefix of every topic.
+        #[arg(long, default_value = "mtrf")]
+        prefix: String,
+        #[arg(long, default_value = "mtrf-bridge")]
+        client_id: String,
+        /// Home Assistant discovery prefix the registry devices are announced under.
+        #[arg(long, default_value = "homeassistant")]
+        discovery_prefix: String,
+    },
+    /// Bridge the adapter to an MQTT broker. Needs a build with the `mqtt` feature.
+    #[cfg(not(feature = "mqtt"))]
+    Mqtt {
+        #[arg(hide = true, num_args = 0.., allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Args)]
+struct Target {
+    #[arg(long, default_value = "txf")]
+    mode: Mode,
+    #[arg(long, required_unless_present = "id")]
+    ch: Option<u8>,
+    /// nooLite-F device address, decimal or 0x-prefixed hex.
+    #[arg(long, value_parser = parse_id)]
+    id: Option<u32>,
+    /// Send to every device of the channel without waiting for answers.
+    #[arg(long, conflicts_with = "id")]
+    broadcast: bool,
+}
+
+impl Target {
+    fn address(&self) -> Result<Address, Error> {
+        let address = match (self.ch, self.id) {
+            (Some(ch), None) if self.broadcast => Address::Broadcast(ch),
+            (Some(ch), None) => Address::Channel(ch),
+            (None, Some(id)) => Address::Id(id),
+            (Some(ch), Some(id)) => Address::IdInChannel(ch, id),
+            (None, None) => bail!("Either --ch or --id is required"),
+        };
+        address.validate(self.mode)?;
+        Ok(address)
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Kind {
+    Relay,
+    Dimmer,
+    Rgb,
+    Remote,
+    Sensor,
+    Other,
+}
+
+impl From<Kind> for DeviceKind {
+    fn from(kind: Kind) -> DeviceKind {
+        match kind {
+            Kind::Relay => DeviceKind::Relay,
+            Kind::Dimmer => DeviceKind::Dimmer,
+            Kind::Rgb => DeviceKind::Rgb,
+            Kind::Remote => DeviceKind::Remote,
+            Kind::Sensor => DeviceKind::Sensor,
+            Kind::Other => DeviceKind::Other,
+        }
+    }
+}
+
+fn parse_id(value: &str) -> Result<u32, Error> {
+    Ok(match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16)?,
+        None => value.parse()?,
+    })
+}
+
+/// Parses the words after `send` into a command.
+fn parse_cmd(words: &[String]) -> Result<Cmd, Error> {
+    let args: Vec<u8> = words[1..]
+        .iter()
+        .map(|arg| arg.parse().map_err(|_| anyhow!("Invalid number:{}", arg)))
+        .collect::<Result<_, _>>()?;
+    let cmd = match (words[0].to_ascii_lowercase().as_str(), args.as_slice()) {
+        ("on", []) => Cmd::On,
+        ("off", []) => Cmd::Off,
+        ("switch", []) | ("toggle", []) => Cmd::Switch,
+        ("bind", []) => Cmd::Bind,
+        ("unbind", []) => Cmd::Unbind,
+        ("brightness", [percent]) => Cmd::SetBrightness(SetBrightness::Fmt1(
+            brightness_from_percent(*percent)
+                .ok_or_else(|| anyhow!("The brightness must be between 0 and 100"))?,
+        )),
+        ("color", [r, g, b]) => Cmd::SetBrightness(SetBrightness::Fmt3([*r, *g, *b])),
+        ("bright-up", []) => Cmd::BrightUp,
+        ("bright-down", []) => Cmd::BrightDown,
+        ("step-up", []) => Cmd::BrightStepUp,
+        ("step-down", []) => Cmd::BrightStepDown,
+        ("stop", []) => Cmd::StopBright,
+        ("load-preset", []) => Cmd::LoadPreset,
+        ("save-preset", []) => Cmd::SavePreset,
+        ("roll-color", []) => Cmd::RollColor,
+        ("switch-color", []) => Cmd::SwitchColor,
+        ("switch-mode", []) => Cmd::SwitchMode,
+        ("read-state", []) => Cmd::ReadState,
+        _ => bail!("Unknown command or wrong arguments: {}", words.join(" ")),
+    };
+    Ok(cmd)
+}
+
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    fn response(&self, resp: &Response) {
+        if self.json {
+            println!("{}", resp.to_json());
+        } else {
+            println!("{}", resp);
+        }
+    }
 
-pub struct Logger;
+    fn print(&self, value: Value, text: String) {
+        if self.json {
+            println!("{}", value);
+        } else {
+            println!("{}", text);
+        }
+    }
+}
+
+/// Discards messages that do not answer a request.
+fn ignore(msg: Response) {
+    log::debug!("Unsolicited message {}", msg);
+}
 
-impl OnMessage for Logger {
-    fn on_message(&mut self, msg: Response) {
-        println!("{}", msg);
+fn port_name(cli: &Cli) -> Result<String, Error> {
+    if let Some(port) = &cli.port {
+        return Ok(port.clone());
     }
+    let port = find_adapters(PROBE_TIMEOUT)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("MTRF-64 adapter not found, pass --port"))?;
+    Ok(port.path.to_string_lossy().into_owned())
+}
+
+fn open<OnMsg: OnMessage + Send + 'static>(cli: &Cli, on_msg: OnMsg) -> Result<Mtrf, Error> {
+    let mut mtrf = Mtrf::new(&port_name(cli)?, on_msg)?;
+    mtrf.set_default_timeout(Duration::from_secs(cli.timeout));
+    Ok(mtrf)
+}
+
+fn load_registry(cli: &Cli) -> Result<Registry, Error> {
+    match &cli.registry {
+        Some(path) => Registry::load(path),
+        None => Ok(Registry::new()),
+    }
+}
+
+fn save_registry(cli: &Cli, registry: &Registry) -> Result<(), Error> {
+    if let Some(path) = &cli.registry {
+        registry.save(path)?;
+    }
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<(), Error> {
+    let out = Output { json: cli.json };
+    match &cli.command {
+        Command::Ports => {
+            for port in list_ports()? {
+                let by_id = port.by_id.as_ref().map(|path| path.display().to_string());
+                out.print(
+                    json!({
+                        "path": port.path.display().to_string(),
+                        "by_id": by_id,
+                        "ftdi": port.is_ftdi(),
+                    }),
+                    format!(
+                        "{}{}{}",
+                        port.path.display(),
+                        by_id.map(|id| format!(" ({})", id)).unwrap_or_default(),
+                        if port.is_ftdi() { " [FTDI]" } else { "" }
+                    ),
+                );
+            }
+        }
+        Command::Listen { mode } => {
+            let mtrf = open(&cli, ignore)?;
+            let messages = mtrf.subscribe(Filter::any());
+            if *mode == Mode::RX || *mode == Mode::RxF {
+                mtrf.send(set_mode(*mode))?;
+            }
+            for msg in messages.iter() {
+                out.response(&msg);
+            }
+            bail!("Adapter disconnected: {}", mtrf.health());
+        }
+        Command::Send { target, cmd } => {
+            let req = target.address()?.request(target.mode, parse_cmd(cmd)?)?;
+            let mtrf = open(&cli, ignore)?;
+            if req.mode == Mode::TxF && req.ctr == CtrRequest::SendCommand {
+                for resp in mtrf.send_request_all(req)? {
+                    out.response(&resp);
+                }
+            } else {
+                out.response(&mtrf.send_request(req)?);
+            }
+        }
+        Command::Bind {
+            mode,
+            ch,
+            name,
+            kind,
+            wait,
+        } => {
+            let mut registry = load_registry(&cli)?;
+            let mtrf = open(&cli, ignore)?;
+            let session = match ch {
+                Some(ch) => BindSession::new(mtrf, *mode, *ch)?,
+                None => BindSession::on_free_channel(mtrf, *mode, &registry)?,
+            };
+            eprintln!(
+                "Binding on {} channel {}, put the device into bind mode",
+                mode,
+                session.ch()
+            );
+            let bound = session.run(Duration::from_secs(*wait))?;
+            if let Some(name) = name {
+                registry.record_bind(&bound.request, &bound.response, name, (*kind).into())?;
+                save_registry(&cli, &registry)?;
+            }
+            out.print(
+                json!({ "mode": mode.name(), "ch": bound.ch, "id": bound.id }),
+                match bound.id {
+                    Some(id) => format!("Bound {:#010x} to channel {}", id, bound.ch),
+                    None => format!("Bound to channel {}", bound.ch),
+                },
+            );
+        }
+        Command::Unbind { target } => {
+            let address = target.address()?;
+            let req = if target.mode == Mode::TxF {
+                // nooLite-F devices forget the adapter themselves.
+                address.request(target.mode, Cmd::Unbind)?
+            } else {
+                ensure!(!target.broadcast, "Unbind does not support --broadcast");
+                Request {
+                    ctr: CtrRequest::UnbindAddressFromChannel,
+                    ..address.request(target.mode, Cmd::Unbind)?
+                }
+            };
+            let mut registry = load_registry(&cli)?;
+            let mtrf = open(&cli, ignore)?;
+            let resp = mtrf.send_request(req)?;
+            for dev in registry.record_unbind(&req, &resp) {
+                eprintln!("Removed {} from the registry", dev);
+            }
+            save_registry(&cli, &registry)?;
+            out.response(&resp);
+        }
+        Command::State { ch, id } => {
+            let mtrf = open(&cli, ignore)?;
+            let state = match (ch, id) {
+                (_, Some(id)) => mtrf.read_state_by_id(*id)?,
+                (Some(ch), None) => mtrf.read_state(*ch)?,
+                (None, None) => bail!("Either --ch or --id is required"),
+            };
+            out.print(state.to_json(), state.to_string());
+        }
+        #[cfg(feature = "http")]
+        Command::Serve { listen } => {
+            let server = tiny_http::Server::http(listen.as_str())
+                .map_err(|err| anyhow!("Failed to listen on {}: {}", listen, err))?;
+            let api = Api::new(load_registry(&cli)?, cli.registry.clone(), |sink| {
+                open(&cli, sink)
+            })?;
+            eprintln!("Listening on {}", listen);
+            api.serve(&server);
+        }
+        #[cfg(feature = "mqtt")]
+        Command::Mqtt {
+            broker,
+            broker_port,
+            prefix,
+            client_id,
+            discovery_prefix,
+        } => {
+            let topics = Topics::new(prefix.as_str());
+            let (client, connection) = mqtt::client::connect(mqtt::client::options(
+                client_id,
+                broker,
+                *broker_port,
+                &topics,
+            ));
+            let discovery = Discovery::new(discovery_prefix.as_str(), topics.clone());
+            let bridge = Bridge::new(client, topics, |publisher| open(&cli, publisher))?;
+            bridge.announce(discovery.configs(&load_registry(&cli)?));
+            mqtt::client::run(&bridge, connection);
+        }
+        #[cfg(not(feature = "mqtt"))]
+        Command::Mqtt { .. } => bail!("Built without MQTT support, rebuild with `--features mqtt`"),
+        Command::ClearChannel { mode, ch, confirm } => {
+            let wipe = Wipe::Channel {
+                mode: *mode,
+                ch: *ch,
+            };
+            let confirmation = match confirm {
+                Some(phrase) => wipe.confirm(phrase)?,
+                None => bail!("Pass --confirm \"{}\" to proceed", wipe),
+            };
+            let registry = Mutex::new(load_registry(&cli)?);
+            let mtrf = open(&cli, ignore)?;
+            let timeout = Duration::from_secs(cli.timeout);
+            let backup = clear(&mtrf, &registry, confirmation, timeout, |backup| {
+                if let Some(path) = &cli.registry {
+                    let backup_path = backup.save_beside(path)?;
+                    eprintln!("Backed up the channel to {}", backup_path.display());
+                }
+                Ok(())
+            })?;
+            save_registry(&cli, &registry.into_inner().unwrap())?;
+            let removed: Vec<String> = backup.entries.iter().map(|dev| dev.full_name()).collect();
+            out.print(
+                json!({ "mode": mode.name(), "ch": ch, "removed": removed }),
+                format!("Cleared channel {} of {}", ch, mode),
+            );
+        }
+    }
+    Ok(())
 }
 
 fn main() {
-    let mut mtrf = Mtrf::new("/dev/tty.usbserial-AL065KM0", Logger).unwrap();
-    // dbg!(mtrf.send_request(Request { mode: Mode::RX, ctr: CtrRequest::BindModeOn, cmd: Cmd::Bind, ch: 1, ..Default::default() })
-    //     .unwrap());
-    thread::sleep_ms(20000);
+    env_logger::init();
+    if let Err(err) = run(Cli::parse()) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use crate::{parse_cmd, Cli, Command};
+    use mtrf::cmd::address::Address;
+    use mtrf::cmd::{Cmd, Mode, SetBrightness};
+
+    fn words(line: &str) -> Vec<String> {
+        line.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    pub fn parse_send() {
+        let cli = Cli::try_parse_from(words("mtrf send --mode txf --ch 3 on")).unwrap();
+        match cli.command {
+            Command::Send { target, cmd } => {
+                assert_eq!(target.mode, Mode::TxF);
+                assert_eq!(target.address().unwrap(), Address::Channel(3));
+                assert_eq!(parse_cmd(&cmd).unwrap(), Cmd::On);
+            }
+            _ => panic!("Expected send"),
+        }
+
+        assert_eq!(
+            parse_cmd(&words("color 1 2 3")).unwrap(),
+            Cmd::SetBrightness(SetBrightness::Fmt3([1, 2, 3]))
+        );
+        assert!(parse_cmd(&words("brightness 101")).is_err());
+        assert!(parse_cmd(&words("on 1")).is_err());
+
+        let cli = Cli::try_parse_from(words("mtrf send --mode tx --id 0x1F on")).unwrap();
+        match cli.command {
+            Command::Send { target, .. } => assert!(target.address().is_err()),
+            _ => panic!("Expected send"),
+        }
+    }
 }
@@ -0,0 +1,199 @@
+//! [`MqttClient`] on top of rumqttc.
+
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Error;
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Outgoing, Packet, QoS};
+
+use crate::mqtt::{Bridge, Message, MqttClient, Topics};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Requests the client may queue before `publish` blocks.
+const CAPACITY: usize = 64;
+/// Pause before the next connection attempt.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct RumqttClient(Client);
+
+impl MqttClient for RumqttClient {
+    fn publish(&self, msg: Message) -> Result<(), Error> {
+        self.0
+            .publish(msg.topic, QoS::AtLeastOnce, msg.retain, msg.payload)?;
+        Ok(())
+    }
+
+    fn try_publish(&self, msg: Message) -> Result<(), Error> {
+        self.0
+            .try_publish(msg.topic, QoS::AtLeastOnce, msg.retain, msg.payload)?;
+        Ok(())
+    }
+
+    fn subscribe(&self, filter: &str) -> Result<(), Error> {
+        self.0.subscribe(filter, QoS::AtLeastOnce)?;
+        Ok(())
+    }
+}
+
+impl RumqttClient {
+    pub fn disconnect(&self) -> Result<(), Error> {
+        self.0.disconnect()?;
+        Ok(())
+    }
+}
+
+/// Connection options with the status topic of `topics` as the last will.
+pub fn options(client_id: &str, host: &str, port: u16, topics: &Topics) -> MqttOptions {
+    let will = topics.will();
+    let mut options = MqttOptions::new(client_id, host, port);
+    options
+        .set_keep_alive(KEEP_ALIVE)
+        .set_last_will(LastWill::new(
+            will.topic,
+            will.payload,
+            QoS::AtLeastOnce,
+            will.retain,
+        ));
+    options
+}
+
+/// Creates the client. Nothing is sent until the connection is [`run`].
+pub fn connect(options: MqttOptions) -> (RumqttClient, Connection) {
+    let (client, connection) = Client::new(options, CAPACITY);
+    (RumqttClient(client), connection)
+}
+
+/// Drives the connection, reconnecting as needed, until the client disconnects.
+///
+/// Commands and the announcements of a connect run on separate threads: the
+/// queue of the client only drains while the connection is polled here.
+pub fn run(bridge: &Bridge<RumqttClient>, mut connection: Connection) {
+    let (tx, rx) = channel();
+    let server = bridge.clone();
+    let commands = thread::spawn(move || server.serve(rx));
+
+    for event in connection.iter() {
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("Connected to the MQTT broker");
+                let announcer = bridge.clone();
+                thread::spawn(move || {
+                    if let Err(err) = announcer.on_connect() {
+                        warn!("Failed to announce the bridge: {}", err);
+                    }
+                });
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let msg = Message::new(publish.topic, publish.payload.to_vec(), publish.retain);
+                if tx.send(msg).is_err() {
+                    break;
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+            Ok(_) => {}
+            Err(err) => {
+                warn!("MQTT connection failed: {}", err);
+                thread::sleep(RECONNECT_DELAY);
+            }
+        }
+    }
+    drop(tx);
+    let _ = commands.join();
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Sender};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::emulator::Emulator;
+    use crate::mqtt::client::{connect, options, run};
+    use crate::mqtt::{Bridge, Message, Topics};
+    use crate::mtrf::Mtrf;
+
+    /// Reads the first header byte and the body of the next packet.
+    fn packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte).ok()?;
+        let kind = byte[0];
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            stream.read_exact(&mut byte).ok()?;
+            len |= ((byte[0] & 0x7F) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).ok()?;
+        Some((kind, body))
+    }
+
+    /// Broker for one client that acknowledges everything and reports the
+    /// topics published to it.
+    fn broker(listener: TcpListener, published: Sender<String>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        while let Some((kind, body)) = packet(&mut stream) {
+            let reply = match kind >> 4 {
+                1 => vec![0x20, 2, 0, 0],
+                3 => {
+                    let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                    let _ = published.send(String::from_utf8_lossy(&body[2..2 + len]).into());
+                    if kind & 0x06 == 0 {
+                        continue;
+                    }
+                    vec![0x40, 2, body[2 + len], body[3 + len]]
+                }
+                8 => vec![0x90, 3, body[0], body[1], 1],
+                12 => vec![0xD0, 0],
+                14 => break,
+                _ => continue,
+            };
+            if stream.write_all(&reply).is_err() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    pub fn announce_more_than_capacity() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, published) = channel();
+        thread::spawn(move || broker(listener, tx));
+
+        let emulator = Emulator::new();
+        let topics = Topics::default();
+        let (client, connection) = connect(options("test", "127.0.0.1", port, &topics));
+        let bridge = Bridge::new(client.clone(), topics, |publisher| {
+            Mtrf::with_transport(emulator.transport(), publisher)
+        })
+        .unwrap();
+        let count = 200;
+        bridge.announce(
+            (0..count)
+                .map(|idx| Message::new(format!("homeassistant/test/{}/config", idx), "{}", true))
+                .collect(),
+        );
+        let runner = bridge.clone();
+        let driver = thread::spawn(move || run(&runner, connection));
+
+        let mut announced = 0;
+        loop {
+            let topic = published.recv_timeout(Duration::from_secs(5)).unwrap();
+            if topic == bridge.topics().status() {
+                break;
+            }
+            announced += 1;
+        }
+        assert_eq!(announced, count);
+        client.disconnect().unwrap();
+        driver.join().unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+//! Home Assistant MQTT discovery for the devices of a [`Registry`].
+//!
+//! - dimmers, RGB controllers and relays of TX/TX-F channels are lights,
+//! - `SensTempHumi` sensors are temperature and humidity sensors with a
+//!   battery binary sensor,
+//! - remotes of RX/RX-F channels are device triggers, one per command.
+
+use serde_json::{json, Value};
+
+use crate::cmd::{Cmd, Mode};
+use crate::mqtt::{Message, Topics};
+use crate::registry::{DeviceEntry, DeviceKind, Registry};
+
+/// Commands a remote may send, each announced as a trigger.
+const REMOTE_COMMANDS: [Cmd; 9] = [
+    Cmd::On,
+    Cmd::Off,
+    Cmd::Switch,
+    Cmd::BrightUp,
+    Cmd::BrightDown,
+    Cmd::BrightBack,
+    Cmd::StopBright,
+    Cmd::LoadPreset,
+    Cmd::SavePreset,
+];
+
+/// Builds discovery configs under a discovery prefix, `homeassistant` by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discovery {
+    prefix: String,
+    topics: Topics,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery::new("homeassistant", Topics::default())
+    }
+}
+
+impl Discovery {
+    pub fn new<P: Into<String>>(prefix: P, topics: Topics) -> Discovery {
+        Discovery {
+            prefix: prefix.into(),
+            topics,
+        }
+    }
+
+    /// Retained configs of every device of the registry.
+    pub fn configs(&self, registry: &Registry) -> Vec<Message> {
+        registry
+            .devices()
+            .iter()
+            .flat_map(|dev| self.device_configs(dev))
+            .collect()
+    }
+
+    /// Retained configs of one device. Devices Home Assistant has no entity
+    /// for, e.g. a dimmer in an RX mode, get none.
+    pub fn device_configs(&self, dev: &DeviceEntry) -> Vec<Message> {
+        self.entities(dev)
+            .into_iter()
+            .map(|(topic, config)| Message::new(topic, config.to_string(), true))
+            .collect()
+    }
+
+    /// Empty retained messages that make Home Assistant forget the device.
+    pub fn removals(&self, dev: &DeviceEntry) -> Vec<Message> {
+        self.entities(dev)
+            .into_iter()
+            .map(|(topic, _)| Message::new(topic, "", true))
+            .collect()
+    }
+
+    fn entities(&self, dev: &DeviceEntry) -> Vec<(String, Value)> {
+        let id = object_id(dev);
+        let tx = dev.mode == Mode::TX || dev.mode == Mode::TxF;
+        match dev.kind {
+            DeviceKind::Dimmer | DeviceKind::Rgb | DeviceKind::Relay if tx => {
+                let color_mode = match dev.kind {
+                    DeviceKind::Dimmer => "brightness",
+                    DeviceKind::Rgb => "rgb",
+                    _ => "onoff",
+                };
+                let config = json!({
+                    "schema": "json",
+                    "command_topic": self.topics.set(dev.mode, dev.ch),
+                    "state_topic": self.topics.state(dev.mode, dev.ch),
+                    "supported_color_modes": [color_mode],
+                });
+                vec![(
+                    self.topic("light", &id, "light"),
+                    self.entity(dev, &id, Value::Null, config),
+                )]
+            }
+            DeviceKind::Sensor if !tx => {
+                let state_topic = self.topics.event_of(dev.mode, dev.ch, &Cmd::SensTempHumi);
+                let sensor = |class: &str, unit: &str| {
+                    json!({
+                        "state_topic": state_topic,
+                        "device_class": class,
+                        "unit_of_measurement": unit,
+                        "state_class": "measurement",
+                        "value_template": format!("{{{{ value_json.{} }}}}", class),
+                    })
+                };
+                let battery = json!({
+                    "state_topic": state_topic,
+                    "device_class": "battery",
+                    "value_template": "{{ 'ON' if value_json.battery_low else 'OFF' }}",
+                });
+                vec![
+                    (
+                        self.topic("sensor", &id, "temperature"),
+                        self.entity(dev, &id, "Temperature".into(), sensor("temperature", "°C")),
+                    ),
+                    (
+                        self.topic("sensor", &id, "humidity"),
+                        self.entity(dev, &id, "Humidity".into(), sensor("humidity", "%")),
+                    ),
+                    (
+                        self.topic("binary_sensor", &id, "battery"),
+                        self.entity(dev, &id, "Battery".into(), battery),
+                    ),
+                ]
+            }
+            DeviceKind::Remote if !tx => REMOTE_COMMANDS
+                .iter()
+                .map(|cmd| {
+                    let config = json!({
+                        "automation_type": "trigger",
+                        "topic": self.topics.event_of(dev.mode, dev.ch, cmd),
+                        "type": "button_short_press",
+                        "subtype": cmd.name(),
+                        "device": device(dev, &id),
+                    });
+                    (self.topic("device_trigger", &id, cmd.name()), config)
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn topic(&self, component: &str, id: &str, object: &str) -> String {
+        format!("{}/{}/{}/{}/config", self.prefix, component, id, object)
+    }
+
+    /// Config of an entity with the fields every entity shares.
+    fn entity(&self, dev: &DeviceEntry, id: &str, name: Value, mut config: Value) -> Value {
+        let object = match &name {
+            Value::String(name) => format!("{}_{}", id, name.to_lowercase()),
+            _ => id.to_owned(),
+        };
+        config["name"] = name;
+        config["unique_id"] = json!(object);
+        config["availability_topic"] = json!(self.topics.status());
+        config["device"] = device(dev, id);
+        config
+    }
+}
+
+fn device(dev: &DeviceEntry, id: &str) -> Value {
+    let mut device = json!({
+        "identifiers": [id],
+        "name": dev.full_name(),
+        "manufacturer": "nooLite",
+    });
+    if let Some(room) = &dev.room {
+        device["suggested_area"] = json!(room);
+    }
+    device
+}
+
+/// The address of nooLite-F devices, otherwise the channel and the name.
+fn object_id(dev: &DeviceEntry) -> String {
+    match dev.id {
+        Some(id) => format!("mtrf_{:08x}", id),
+        None => {
+            let name: String = dev
+                .full_name()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!(
+                "mtrf_{}_{}_{}",
+                dev.mode.name(),
+                dev.ch,
+                name.to_lowercase()
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+
+    use crate::cmd::Mode;
+    use crate::mqtt::homeassistant::Discovery;
+    use crate::mqtt::Message;
+    use crate::registry::{DeviceEntry, DeviceKind, Registry};
+
+    fn config(messages: &[Message], topic: &str) -> Value {
+        let msg = messages.iter().find(|msg| msg.topic == topic).unwrap();
+        assert!(msg.retain);
+        serde_json::from_slice(&msg.payload).unwrap()
+    }
+
+    #[test]
+    pub fn discovery_configs() {
+        let mut registry = Registry::new();
+        let devices = [
+            ("ceiling", DeviceKind::Dimmer, Mode::TxF, 0, Some(0xAB)),
+            ("strip", DeviceKind::Rgb, Mode::TX, 1, None),
+            ("climate", DeviceKind::Sensor, Mode::RX, 2, None),
+            ("remote", DeviceKind::Remote, Mode::RxF, 3, Some(0x77)),
+            ("unknown", DeviceKind::Dimmer, Mode::RX, 4, None),
+        ];
+        for (name, kind, mode, ch, id) in devices.iter() {
+            registry
+                .add(DeviceEntry {
+                    name: name.to_string(),
+                    room: Some("hall".to_owned()),
+                    kind: *kind,
+                    mode: *mode,
+                    ch: *ch,
+                    id: *id,
+                })
+                .unwrap();
+        }
+        let discovery = Discovery::default();
+        let messages = discovery.configs(&registry);
+        assert_eq!(messages.len(), 1 + 1 + 3 + 9);
+
+        let light = config(&messages, "homeassistant/light/mtrf_000000ab/light/config");
+        assert_eq!(light["command_topic"], json!("mtrf/txf/0/set"));
+        assert_eq!(light["state_topic"], json!("mtrf/txf/0/state"));
+        assert_eq!(light["supported_color_modes"], json!(["brightness"]));
+        assert_eq!(light["availability_topic"], json!("mtrf/status"));
+        assert_eq!(light["device"]["name"], json!("hall.ceiling"));
+        let strip = config(
+            &messages,
+            "homeassistant/light/mtrf_tx_1_hall_strip/light/config",
+        );
+        assert_eq!(strip["supported_color_modes"], json!(["rgb"]));
+
+        let temperature = config(
+            &messages,
+            "homeassistant/sensor/mtrf_rx_2_hall_climate/temperature/config",
+        );
+        assert_eq!(
+            temperature["state_topic"],
+            json!("mtrf/rx/2/sens_temp_humi")
+        );
+        assert_eq!(
+            temperature["value_template"],
+            json!("{{ value_json.temperature }}")
+        );
+        assert_eq!(
+            temperature["unique_id"],
+            json!("mtrf_rx_2_hall_climate_temperature")
+        );
+
+        let trigger = config(
+            &messages,
+            "homeassistant/device_trigger/mtrf_00000077/switch/config",
+        );
+        assert_eq!(trigger["topic"], json!("mtrf/rxf/3/switch"));
+        assert_eq!(trigger["subtype"], json!("switch"));
+
+        let removals = discovery.removals(&registry.devices()[0]);
+        assert_eq!(
+            removals[0].topic,
+            "homeassistant/light/mtrf_000000ab/light/config"
+        );
+        assert!(removals[0].payload.is_empty());
+    }
+}
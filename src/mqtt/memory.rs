@@ -0,0 +1,181 @@
+//! In-process stand-in for an MQTT broker.
+//!
+//! Keeps retained messages, delivers publishes to matching subscriptions and
+//! sends the last will of a client that is [`kill`](MemoryClient::kill)ed.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+
+use crate::mqtt::{Message, MqttClient};
+
+struct Session {
+    filters: Vec<String>,
+    inbox: Sender<Message>,
+    will: Option<Message>,
+}
+
+#[derive(Default)]
+struct State {
+    retained: HashMap<String, Vec<u8>>,
+    sessions: HashMap<usize, Session>,
+    next_id: usize,
+}
+
+impl State {
+    fn route(&mut self, msg: Message) {
+        if msg.retain {
+            if msg.payload.is_empty() {
+                self.retained.remove(&msg.topic);
+            } else {
+                self.retained.insert(msg.topic.clone(), msg.payload.clone());
+            }
+        }
+        for session in self.sessions.values() {
+            if session
+                .filters
+                .iter()
+                .any(|filter| matches(filter, &msg.topic))
+            {
+                // Live delivery does not carry the retain flag, as with a real broker.
+                let _ = session.inbox.send(Message {
+                    retain: false,
+                    ..msg.clone()
+                });
+            }
+        }
+    }
+}
+
+/// Handle to the broker. Clones share the same broker.
+#[derive(Clone, Default)]
+pub struct MemoryBroker {
+    state: Arc<Mutex<State>>,
+}
+
+impl MemoryBroker {
+    pub fn new() -> MemoryBroker {
+        MemoryBroker::default()
+    }
+
+    /// Connects a client. Messages of its subscriptions arrive on the receiver.
+    pub fn connect(&self, will: Option<Message>) -> (MemoryClient, Receiver<Message>) {
+        let (tx, rx) = channel();
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.sessions.insert(
+            id,
+            Session {
+                filters: vec![],
+                inbox: tx,
+                will,
+            },
+        );
+        let client = MemoryClient {
+            broker: self.clone(),
+            id,
+        };
+        (client, rx)
+    }
+
+    pub fn retained(&self, topic: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().retained.get(topic).cloned()
+    }
+}
+
+/// Client session of a [`MemoryBroker`].
+#[derive(Clone)]
+pub struct MemoryClient {
+    broker: MemoryBroker,
+    id: usize,
+}
+
+impl MemoryClient {
+    /// Closes the session cleanly, the last will is discarded.
+    pub fn disconnect(&self) {
+        self.broker.state.lock().unwrap().sessions.remove(&self.id);
+    }
+
+    /// Drops the session as if the connection was lost, publishing the last will.
+    pub fn kill(&self) {
+        let mut state = self.broker.state.lock().unwrap();
+        if let Some(will) = state.sessions.remove(&self.id).and_then(|s| s.will) {
+            state.route(will);
+        }
+    }
+}
+
+impl MqttClient for MemoryClient {
+    fn publish(&self, msg: Message) -> Result<(), Error> {
+        let mut state = self.broker.state.lock().unwrap();
+        ensure!(state.sessions.contains_key(&self.id), "Not connected");
+        state.route(msg);
+        Ok(())
+    }
+
+    fn subscribe(&self, filter: &str) -> Result<(), Error> {
+        let mut state = self.broker.state.lock().unwrap();
+        let state = &mut *state;
+        let session = state
+            .sessions
+            .get_mut(&self.id)
+            .ok_or_else(|| anyhow!("Not connected"))?;
+        for (topic, payload) in &state.retained {
+            if matches(filter, topic) {
+                let _ = session
+                    .inbox
+                    .send(Message::new(topic.as_str(), payload.clone(), true));
+            }
+        }
+        session.filters.push(filter.to_owned());
+        Ok(())
+    }
+}
+
+/// Whether `topic` matches a subscription filter with `+` and `#` wildcards.
+pub fn matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mqtt::memory::{matches, MemoryBroker};
+    use crate::mqtt::{Message, MqttClient};
+
+    #[test]
+    pub fn retained_and_wildcards() {
+        assert!(matches("mtrf/+/+/set", "mtrf/txf/3/set"));
+        assert!(!matches("mtrf/+/+/set", "mtrf/txf/3/state"));
+        assert!(matches("mtrf/#", "mtrf/status"));
+        assert!(!matches("mtrf/+", "mtrf/txf/3"));
+
+        let broker = MemoryBroker::new();
+        let (client, _) = broker.connect(None);
+        client
+            .publish(Message::new("mtrf/status", "online", true))
+            .unwrap();
+        let (late, inbox) = broker.connect(None);
+        late.subscribe("mtrf/+").unwrap();
+        assert_eq!(
+            inbox.try_recv().unwrap(),
+            Message::new("mtrf/status", "online", true)
+        );
+
+        client.disconnect();
+        assert!(client.publish(Message::new("mtrf/x", "", false)).is_err());
+        late.publish(Message::new("mtrf/status", "", true)).unwrap();
+        assert_eq!(broker.retained("mtrf/status"), None);
+    }
+}
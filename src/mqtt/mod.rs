@@ -0,0 +1,537 @@
+//! Bridge between the adapter and an MQTT broker.
+//!
+//! Topics live under a prefix, `mtrf` by default:
+//!
+//! - `mtrf/status`: `online` or `offline`, retained and used as the last will.
+//! - `mtrf/<mode>/<ch>/<cmd>`: every message of the adapter as JSON, e.g.
+//!   `mtrf/rxf/2/sens_temp_humi`.
+//! - `mtrf/<mode>/<ch>/set`: commands for TX and TX-F channels: `ON`, `OFF`,
+//!   `TOGGLE`, a brightness of 0–255 or
+//!   `{"state": "ON", "brightness": 128, "color": {"r": 255, "g": 0, "b": 0}}`.
+//! - `mtrf/<mode>/<ch>/state`: the last known state of a channel, retained.
+//!
+//! [`homeassistant`] adds discovery configs for the devices of a registry.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use crate::cmd::address::Address;
+use crate::cmd::payload::Payload;
+use crate::cmd::response::Response;
+use crate::cmd::state::{DeviceState, LoadState};
+use crate::cmd::{Cmd, Mode, SetBrightness, CHANNELS};
+use crate::mtrf::{Mtrf, OnMessage};
+
+pub mod client;
+pub mod homeassistant;
+pub mod memory;
+
+pub const ONLINE: &str = "online";
+pub const OFFLINE: &str = "offline";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+impl Message {
+    pub fn new<T: Into<String>, P: Into<Vec<u8>>>(topic: T, payload: P, retain: bool) -> Message {
+        Message {
+            topic: topic.into(),
+            payload: payload.into(),
+            retain,
+        }
+    }
+}
+
+/// Connection to a broker. Clones share the connection.
+pub trait MqttClient: Clone + Send + Sync + 'static {
+    /// May wait until the connection makes room for the message.
+    fn publish(&self, msg: Message) -> Result<(), Error>;
+
+    /// Fails instead of waiting when the outgoing queue is full.
+    fn try_publish(&self, msg: Message) -> Result<(), Error> {
+        self.publish(msg)
+    }
+
+    fn subscribe(&self, filter: &str) -> Result<(), Error>;
+}
+
+/// Topic layout of the bridge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topics {
+    prefix: String,
+}
+
+impl Default for Topics {
+    fn default() -> Self {
+        Topics::new("mtrf")
+    }
+}
+
+impl Topics {
+    pub fn new<P: Into<String>>(prefix: P) -> Topics {
+        Topics {
+            prefix: prefix.into(),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn status(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    /// `offline` on the status topic, to be registered as the last will.
+    pub fn will(&self) -> Message {
+        Message::new(self.status(), OFFLINE, true)
+    }
+
+    pub fn event(&self, resp: &Response) -> String {
+        self.event_of(resp.mode, resp.ch, &resp.cmd)
+    }
+
+    /// Topic of the messages with `cmd` arriving on the channel.
+    pub fn event_of(&self, mode: Mode, ch: u8, cmd: &Cmd) -> String {
+        format!("{}/{}", self.channel(mode, ch), cmd.name())
+    }
+
+    pub fn state(&self, mode: Mode, ch: u8) -> String {
+        format!("{}/state", self.channel(mode, ch))
+    }
+
+    pub fn set(&self, mode: Mode, ch: u8) -> String {
+        format!("{}/set", self.channel(mode, ch))
+    }
+
+    /// Filter matching the command topics of every channel.
+    pub fn set_filter(&self) -> String {
+        format!("{}/+/+/set", self.prefix)
+    }
+
+    /// Mode and channel of a command topic.
+    pub fn parse_set(&self, topic: &str) -> Option<(Mode, u8)> {
+        let rest = topic.strip_prefix(&self.prefix)?.strip_prefix('/')?;
+        let mut parts = rest.split('/');
+        let mode = parts.next()?.parse().ok()?;
+        let ch = parts.next()?.parse().ok().filter(|ch| *ch < CHANNELS)?;
+        match (parts.next(), parts.next()) {
+            (Some("set"), None) => Some((mode, ch)),
+            _ => None,
+        }
+    }
+
+    fn channel(&self, mode: Mode, ch: u8) -> String {
+        format!("{}/{}/{}", self.prefix, mode.name(), ch)
+    }
+}
+
+/// Last known output of a channel.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct ChannelState {
+    on: bool,
+    brightness: Option<u8>,
+    color: Option<[u8; 3]>,
+}
+
+impl ChannelState {
+    /// Applies a command confirmed by the adapter. Returns `false` if the
+    /// command does not tell the new output.
+    fn apply(&mut self, cmd: &Cmd) -> bool {
+        match cmd {
+            Cmd::On => self.on = true,
+            Cmd::Off => self.on = false,
+            Cmd::Switch => self.on = !self.on,
+            Cmd::SetBrightness(SetBrightness::Fmt1(level)) => {
+                self.on = *level > 0;
+                self.brightness = Some(*level);
+            }
+            Cmd::SetBrightness(SetBrightness::Fmt3(color)) => {
+                self.on = *color != [0; 3];
+                self.color = Some(*color);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn to_json(self) -> Value {
+        let mut state = json!({ "state": if self.on { "ON" } else { "OFF" } });
+        if let Some(brightness) = self.brightness {
+            state["brightness"] = json!(brightness);
+        }
+        if let Some([r, g, b]) = self.color {
+            state["color"] = json!({ "r": r, "g": g, "b": b });
+        }
+        state
+    }
+}
+
+struct Shared<C> {
+    client: C,
+    topics: Topics,
+    states: Mutex<HashMap<(Mode, u8), ChannelState>>,
+    announcements: Mutex<Vec<Message>>,
+}
+
+impl<C: MqttClient> Shared<C> {
+    /// Never waits, so the adapter worker is not stalled by a broker outage.
+    /// The message is dropped if it cannot be queued.
+    fn publish(&self, msg: Message) {
+        let topic = msg.topic.clone();
+        if let Err(err) = self.client.try_publish(msg) {
+            warn!("Dropped the message on {}: {}", topic, err);
+        }
+    }
+
+    /// Publishes the message as an event. Returns `true` if it reported the
+    /// state of the channel.
+    fn publish_response(&self, resp: &Response) -> bool {
+        self.publish(Message::new(
+            self.topics.event(resp),
+            resp.to_json().to_string(),
+            false,
+        ));
+        match resp.payload() {
+            Payload::State(DeviceState::Main {
+                state, brightness, ..
+            }) => {
+                self.update(resp.mode, resp.ch, |channel| {
+                    channel.on = state != LoadState::Off;
+                    channel.brightness = Some(brightness);
+                    true
+                });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn update<F: FnOnce(&mut ChannelState) -> bool>(&self, mode: Mode, ch: u8, f: F) {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry((mode, ch)).or_default();
+        if f(state) {
+            let msg = Message::new(
+                self.topics.state(mode, ch),
+                state.to_json().to_string(),
+                true,
+            );
+            drop(states);
+            self.publish(msg);
+        }
+    }
+}
+
+/// Publishes the messages the adapter sends on its own, installed by [`Bridge::new`].
+pub struct Publisher<C> {
+    shared: Arc<Shared<C>>,
+}
+
+impl<C: MqttClient> OnMessage for Publisher<C> {
+    fn on_message(&mut self, msg: Response) {
+        self.shared.publish_response(&msg);
+    }
+}
+
+/// Runs the commands of the command topics and publishes what the adapter reports.
+pub struct Bridge<C> {
+    mtrf: Mtrf,
+    shared: Arc<Shared<C>>,
+}
+
+impl<C> Clone for Bridge<C> {
+    fn clone(&self) -> Self {
+        Bridge {
+            mtrf: self.mtrf.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<C: MqttClient> Bridge<C> {
+    /// Opens the adapter with `open`, which gets the handler for its unsolicited messages.
+    ///
+    /// `Bridge::new(client, topics, |publisher| Mtrf::new(port, publisher))`
+    pub fn new<F>(client: C, topics: Topics, open: F) -> Result<Bridge<C>, Error>
+    where
+        F: FnOnce(Publisher<C>) -> Result<Mtrf, Error>,
+    {
+        let shared = Arc::new(Shared {
+            client,
+            topics,
+            states: Mutex::new(HashMap::new()),
+            announcements: Mutex::new(vec![]),
+        });
+        let mtrf = open(Publisher {
+            shared: shared.clone(),
+        })?;
+        Ok(Bridge { mtrf, shared })
+    }
+
+    pub fn mtrf(&self) -> &Mtrf {
+        &self.mtrf
+    }
+
+    pub fn client(&self) -> &C {
+        &self.shared.client
+    }
+
+    pub fn topics(&self) -> &Topics {
+        &self.shared.topics
+    }
+
+    /// Subscribes to the command topics and marks the bridge online. Call it
+    /// after every connect, a clean session loses the subscriptions.
+    pub fn on_connect(&self) -> Result<(), Error> {
+        self.shared.client.subscribe(&self.topics().set_filter())?;
+        for msg in self.shared.announcements.lock().unwrap().iter() {
+            self.shared.client.publish(msg.clone())?;
+        }
+        self.shared
+            .client
+            .publish(Message::new(self.topics().status(), ONLINE, true))
+    }
+
+    /// Sets the messages published on every connect, e.g. the Home Assistant
+    /// discovery configs. Takes effect on the next connect.
+    pub fn announce(&self, messages: Vec<Message>) {
+        *self.shared.announcements.lock().unwrap() = messages;
+    }
+
+    /// Marks the bridge offline before a planned shutdown. The last will covers the rest.
+    pub fn shutdown(&self) -> Result<(), Error> {
+        self.shared
+            .client
+            .publish(Message::new(self.topics().status(), OFFLINE, true))
+    }
+
+    /// Sends the command of a message from a command topic.
+    pub fn on_command(&self, msg: &Message) -> Result<(), Error> {
+        if msg.retain {
+            // A retained command would be replayed on every restart.
+            debug!("Ignore retained command on {}", msg.topic);
+            return Ok(());
+        }
+        let (mode, ch) = self
+            .topics()
+            .parse_set(&msg.topic)
+            .ok_or_else(|| anyhow!("Not a command topic: {}", msg.topic))?;
+        let cmd = parse_command(&msg.payload)?;
+        ensure!(
+            mode == Mode::TX || mode == Mode::TxF,
+            "Channels of {} take no commands",
+            mode
+        );
+        let req = Address::Channel(ch).request(mode, cmd)?;
+        let answers = if mode == Mode::TxF {
+            self.mtrf.send_request_all(req)?
+        } else {
+            vec![self.mtrf.send_request(req)?]
+        };
+        let mut reported = false;
+        for resp in &answers {
+            reported |= self.shared.publish_response(resp);
+        }
+        if !reported {
+            self.shared.update(mode, ch, |state| state.apply(&cmd));
+        }
+        Ok(())
+    }
+
+    /// Runs the commands until the channel closes, logging the failed ones.
+    pub fn serve(&self, messages: Receiver<Message>) {
+        for msg in messages {
+            if let Err(err) = self.on_command(&msg) {
+                warn!("Command on {} failed: {}", msg.topic, err);
+            }
+        }
+    }
+}
+
+/// Parses the payload of a command topic.
+fn parse_command(payload: &[u8]) -> Result<Cmd, Error> {
+    let text = str::from_utf8(payload)?.trim();
+    match text.to_ascii_uppercase().as_str() {
+        "ON" => return Ok(Cmd::On),
+        "OFF" => return Ok(Cmd::Off),
+        "TOGGLE" => return Ok(Cmd::Switch),
+        _ => {}
+    }
+    if let Ok(level) = text.parse::<u8>() {
+        return Ok(Cmd::SetBrightness(SetBrightness::Fmt1(level)));
+    }
+
+    let value: Value =
+        serde_json::from_str(text).map_err(|_| anyhow!("Unknown command: {}", text))?;
+    let byte = |value: &Value| {
+        value
+            .as_u64()
+            .and_then(|value| u8::try_from(value).ok())
+            .ok_or_else(|| anyhow!("Expected a number between 0 and 255: {}", value))
+    };
+    if value["state"] == "OFF" {
+        return Ok(Cmd::Off);
+    }
+    let brightness = match value.get("brightness") {
+        Some(brightness) => Some(byte(brightness)?),
+        None => None,
+    };
+    if let Some(color) = value.get("color") {
+        let mut rgb = [byte(&color["r"])?, byte(&color["g"])?, byte(&color["b"])?];
+        if let Some(brightness) = brightness {
+            for c in rgb.iter_mut() {
+                *c = (*c as u16 * brightness as u16 / 255) as u8;
+            }
+        }
+        return Ok(Cmd::SetBrightness(SetBrightness::Fmt3(rgb)));
+    }
+    match (brightness, value["state"].as_str()) {
+        (Some(level), _) => Ok(Cmd::SetBrightness(SetBrightness::Fmt1(level))),
+        (None, Some("ON")) => Ok(Cmd::On),
+        (None, Some("TOGGLE")) => Ok(Cmd::Switch),
+        _ => bail!("Unknown command: {}", text),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::Receiver;
+    use std::thread;
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+
+    use crate::cmd::request::bind;
+    use crate::cmd::{Cmd, Mode, SetBrightness};
+    use crate::emulator::{Emulator, SimDevice};
+    use crate::mqtt::memory::MemoryBroker;
+    use crate::mqtt::{parse_command, Bridge, Message, MqttClient, Topics};
+    use crate::mtrf::Mtrf;
+
+    /// Next message on `topic`, skipping the others.
+    fn next_on(rx: &Receiver<Message>, topic: &str) -> Value {
+        loop {
+            let msg = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+            if msg.topic == topic {
+                return serde_json::from_slice(&msg.payload)
+                    .unwrap_or_else(|_| Value::String(String::from_utf8(msg.payload).unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    pub fn topics_and_commands() {
+        let topics = Topics::default();
+        assert_eq!(topics.parse_set("mtrf/txf/3/set"), Some((Mode::TxF, 3)));
+        assert_eq!(
+            topics.parse_set(&topics.set(Mode::TX, 63)),
+            Some((Mode::TX, 63))
+        );
+        assert_eq!(topics.parse_set("mtrf/txf/64/set"), None);
+        assert_eq!(topics.parse_set("mtrf/txf/3/state"), None);
+        assert_eq!(topics.parse_set("other/txf/3/set"), None);
+
+        assert_eq!(parse_command(b"on").unwrap(), Cmd::On);
+        assert_eq!(parse_command(b"TOGGLE").unwrap(), Cmd::Switch);
+        assert_eq!(
+            parse_command(b"200").unwrap(),
+            Cmd::SetBrightness(SetBrightness::Fmt1(200))
+        );
+        assert_eq!(
+            parse_command(
+                br#"{"state": "ON", "color": {"r": 255, "g": 0, "b": 50}, "brightness": 51}"#
+            )
+            .unwrap(),
+            Cmd::SetBrightness(SetBrightness::Fmt3([51, 0, 10]))
+        );
+        assert_eq!(
+            parse_command(br#"{"state": "OFF", "brightness": 9}"#).unwrap(),
+            Cmd::Off
+        );
+        assert!(parse_command(b"300").is_err());
+        assert!(parse_command(b"blink").is_err());
+    }
+
+    #[test]
+    pub fn bridge_through_broker() {
+        let emulator = Emulator::new();
+        emulator.add_device(SimDevice::new(0x10));
+        let broker = MemoryBroker::new();
+        let topics = Topics::default();
+        let (client, commands) = broker.connect(Some(topics.will()));
+        let bridge = Bridge::new(client.clone(), topics, |publisher| {
+            Mtrf::with_transport(emulator.transport(), publisher)
+        })
+        .unwrap();
+        bridge.mtrf().send_request(bind(Mode::TxF, 3)).unwrap();
+
+        let (observer, events) = broker.connect(None);
+        observer.subscribe("mtrf/#").unwrap();
+        let config = Message::new("homeassistant/light/a/light/config", "{}", true);
+        bridge.announce(vec![config.clone()]);
+        bridge.on_connect().unwrap();
+        assert_eq!(next_on(&events, "mtrf/status"), json!("online"));
+        assert_eq!(broker.retained(&config.topic), Some(config.payload));
+        let server = bridge.clone();
+        thread::spawn(move || server.serve(commands));
+
+        observer
+            .publish(Message::new("mtrf/txf/3/set", "ON", false))
+            .unwrap();
+        assert_eq!(next_on(&events, "mtrf/txf/3/on")["id"], json!(0x10));
+        assert_eq!(
+            next_on(&events, "mtrf/txf/3/state"),
+            json!({ "state": "ON" })
+        );
+        assert!(emulator.device(0x10).unwrap().on);
+
+        observer
+            .publish(Message::new("mtrf/txf/3/set", "128", false))
+            .unwrap();
+        assert_eq!(
+            next_on(&events, "mtrf/txf/3/state"),
+            json!({ "state": "ON", "brightness": 128 })
+        );
+        assert_eq!(emulator.device(0x10).unwrap().brightness, 128);
+        observer
+            .publish(Message::new("mtrf/txf/3/set", r#"{"state": "OFF"}"#, false))
+            .unwrap();
+        assert_eq!(next_on(&events, "mtrf/txf/3/state")["state"], json!("OFF"));
+        assert_eq!(
+            broker.retained("mtrf/txf/3/state"),
+            Some(br#"{"brightness":128,"state":"OFF"}"#.to_vec())
+        );
+
+        // Messages the adapter sends on its own.
+        bridge.mtrf().send(bind(Mode::RxF, 2)).unwrap();
+        while emulator.bind_mode().is_none() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        emulator.press(Mode::RxF, 0x77, Cmd::Bind);
+        next_on(&events, "mtrf/rxf/2/bind");
+        emulator.press_raw(
+            Mode::RxF,
+            0x77,
+            Cmd::SensTempHumi.as_u8(),
+            7,
+            [215, 0x20, 40, 0],
+        );
+        let reading = next_on(&events, "mtrf/rxf/2/sens_temp_humi");
+        assert_eq!(reading["temperature"], json!(21.5));
+        assert_eq!(reading["humidity"], json!(40));
+
+        client.kill();
+        assert_eq!(next_on(&events, "mtrf/status"), json!("offline"));
+        assert_eq!(broker.retained("mtrf/status"), Some(b"offline".to_vec()));
+    }
+}
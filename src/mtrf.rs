@@ -1,34 +1,321 @@
 use std::convert::TryFrom;
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::fmt;
+use std::iter;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{
+    channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError,
+};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Error;
 
+use crate::bus::{EventBus, Filter, Listener, Subscription};
+use crate::cmd::decoder::{DecoderStats, FrameDecoder};
+use crate::cmd::payload::Payload;
 use crate::cmd::request::Request;
 use crate::cmd::response::Response;
-use crate::cmd::MESSAGE_LENGTH;
-use serial::core::BaudRate::Baud9600;
-use serial::core::CharSize::Bits8;
-use serial::core::FlowControl::FlowNone;
-use serial::core::Parity::ParityNone;
-use serial::core::StopBits::Stop1;
-use serial::{PortSettings, SerialPort, SystemPort};
-use std::io::{Read, Write};
-
-const SETTINGS: PortSettings = PortSettings {
-    baud_rate: Baud9600,
-    char_size: Bits8,
-    parity: ParityNone,
-    stop_bits: Stop1,
-    flow_control: FlowNone,
-};
+use crate::cmd::state::DeviceState;
+use crate::cmd::{Cmd, CtrRequest, CtrResponse, Mode, MESSAGE_LENGTH};
+use crate::error::RequestError;
+use crate::handler::{Filtered, Mapped, Tee};
+use crate::pending::{PendingKey, PendingTable};
+use crate::supervisor::{ConnectionEvent, DisconnectedPolicy, SupervisorConfig};
+use crate::transport::{Connect, SerialTransport, Transport};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Packets buffered for a collecting request, beyond that the worker waits
+/// for the caller.
+const MAX_PACKETS: usize = 64;
+
+/// State of the worker thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    Alive,
+    /// Stopped by [`Mtrf::close`] or by dropping the last handle.
+    Closed,
+    /// Stopped by a transport error.
+    Failed(String),
+    /// The link failed and a supervised worker is reconnecting.
+    Disconnected(String),
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Alive => write!(f, "Alive"),
+            Health::Closed => write!(f, "Closed"),
+            Health::Failed(err) => write!(f, "Failed({})", err),
+            Health::Disconnected(err) => write!(f, "Disconnected({})", err),
+        }
+    }
+}
+
+type DisconnectHandler = Box<dyn FnMut(&Health) + Send>;
+type ConnectionHandler = Box<dyn FnMut(&ConnectionEvent) + Send>;
+
+/// Worker state shared with the handles.
+struct Status {
+    shutdown: AtomicBool,
+    health: Mutex<Health>,
+    policy: DisconnectedPolicy,
+    /// `None` once the worker has stopped.
+    on_disconnect: Mutex<Option<Vec<DisconnectHandler>>>,
+    on_connection: Mutex<Vec<ConnectionHandler>>,
+}
+
+impl Status {
+    fn notify(&self, event: &ConnectionEvent) {
+        for handler in self.on_connection.lock().unwrap().iter_mut() {
+            handler(event);
+        }
+    }
+
+    fn finish(&self, health: Health) {
+        *self.health.lock().unwrap() = health.clone();
+        let handlers = self.on_disconnect.lock().unwrap().take();
+        for mut handler in handlers.into_iter().flatten() {
+            handler(&health);
+        }
+    }
+}
+
+struct Inner {
+    join: Mutex<Option<JoinHandle<()>>>,
+    req_tx: Sender<Request>,
+    pending: Arc<Mutex<PendingTable<SyncSender<Response>>>>,
+    stats: Arc<Mutex<DecoderStats>>,
+    status: Arc<Status>,
+    bus: EventBus,
+    seq: AtomicU64,
+}
+
+impl Inner {
+    fn close(&self) {
+        self.status.shutdown.store(true, Ordering::SeqCst);
+        let join = self.join.lock().unwrap().take();
+        if let Some(join) = join {
+            // The last handle may be dropped by the worker itself, e.g. from `OnMessage`.
+            if join.thread().id() != thread::current().id() && join.join().is_err() {
+                error!("Mtrf worker panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// The state owned by the worker thread.
+struct Worker<OnMsg> {
+    req_rx: Receiver<Request>,
+    pending: Arc<Mutex<PendingTable<SyncSender<Response>>>>,
+    stats: Arc<Mutex<DecoderStats>>,
+    status: Arc<Status>,
+    decoder: FrameDecoder,
+    bus: EventBus,
+    on_msg: OnMsg,
+}
+
+impl<OnMsg: OnMessage> Worker<OnMsg> {
+    fn new(on_msg: OnMsg, health: Health, policy: DisconnectedPolicy) -> (Worker<OnMsg>, Inner) {
+        let (req_tx, req_rx) = channel();
+        let pending = Arc::new(Mutex::new(PendingTable::default()));
+        let stats = Arc::new(Mutex::new(DecoderStats::default()));
+        let status = Arc::new(Status {
+            shutdown: AtomicBool::new(false),
+            health: Mutex::new(health),
+            policy,
+            on_disconnect: Mutex::new(Some(vec![])),
+            on_connection: Mutex::new(vec![]),
+        });
+        let bus = EventBus::new();
+        let inner = Inner {
+            join: Mutex::new(None),
+            req_tx,
+            pending: pending.clone(),
+            stats: stats.clone(),
+            status: status.clone(),
+            bus: bus.clone(),
+            seq: AtomicU64::new(0),
+        };
+        let worker = Worker {
+            req_rx,
+            pending,
+            stats,
+            status,
+            decoder: FrameDecoder::new(),
+            bus,
+            on_msg,
+        };
+        (worker, inner)
+    }
 
+    /// Serves the transport until it fails or the handles close it.
+    fn session<T: Transport>(&mut self, transport: &mut T) -> Health {
+        let mut buf = [0; MESSAGE_LENGTH];
+        loop {
+            if self.status.shutdown.load(Ordering::SeqCst) {
+                // Flush what the handles queued before closing.
+                while let Ok(req) = self.req_rx.try_recv() {
+                    debug!("Write request {}", req);
+                    if let Err(err) = transport.write_frame(&req.to_message()) {
+                        warn!("Failed to write request {}", err);
+                        return Health::Failed(err.to_string());
+                    }
+                }
+                return Health::Closed;
+            }
+
+            match self.req_rx.try_recv() {
+                Ok(req) => {
+                    debug!("Write request {}", req);
+                    if let Err(err) = transport.write_frame(&req.to_message()) {
+                        warn!("Failed to write request {}", err);
+                        return Health::Failed(err.to_string());
+                    }
+                }
+                Err(TryRecvError::Empty) => {
+                    // no-op
+                }
+                Err(TryRecvError::Disconnected) => {
+                    info!("Request channel disconnected");
+                    return Health::Closed;
+                }
+            }
+
+            let count = match transport.read(&mut buf) {
+                Ok(0) => {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+                Ok(count) => count,
+                Err(err) => {
+                    error!("Failed to read response. {}", err);
+                    return Health::Failed(err.to_string());
+                }
+            };
+
+            self.decoder.push(&buf[..count]);
+            let decoder = &mut self.decoder;
+            let frames: Vec<_> = iter::from_fn(|| decoder.next_frame()).collect();
+            {
+                let mut shared = self.stats.lock().unwrap();
+                if self.decoder.stats().resyncs != shared.resyncs {
+                    warn!(
+                        "Frame stream realigned, {} bytes dropped so far",
+                        self.decoder.stats().dropped_bytes
+                    );
+                }
+                *shared = self.decoder.stats();
+            }
+
+            for msg in frames {
+                match Response::try_from(msg) {
+                    Ok(resp) => {
+                        debug!("Receive msg:{:?}", resp);
+                        let reply = self.pending.lock().unwrap().route(&resp);
+                        match reply {
+                            Some(reply) if reply.send(resp).is_ok() => {}
+                            _ => {
+                                self.bus.publish(&resp);
+                                self.on_msg.on_message(resp);
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        warn!("Failed to decode response {}: msg:[{:?}]", err, msg);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keeps reconnecting until the handles close the worker.
+    fn supervise<C: Connect>(&mut self, mut connector: C, config: SupervisorConfig) -> Health {
+        let mut delay = config.backoff.initial;
+        loop {
+            if self.status.shutdown.load(Ordering::SeqCst) {
+                return Health::Closed;
+            }
+
+            let mut transport = match Self::open(&mut connector, &config) {
+                Ok(transport) => transport,
+                Err(err) => {
+                    debug!("Failed to connect: {}", err);
+                    self.wait_disconnected(delay, config.policy);
+                    delay = config.backoff.next(delay);
+                    continue;
+                }
+            };
+            delay = config.backoff.initial;
+            // Bytes of a frame cut short by the failure must not prefix the next one.
+            self.decoder.clear();
+            *self.status.health.lock().unwrap() = Health::Alive;
+            info!("Adapter connected");
+            self.status.notify(&ConnectionEvent::Reconnected);
+
+            match self.session(&mut transport) {
+                Health::Failed(err) => {
+                    warn!("Adapter disconnected: {}", err);
+                    *self.status.health.lock().unwrap() = Health::Disconnected(err.clone());
+                    if config.policy == DisconnectedPolicy::FailFast {
+                        self.pending.lock().unwrap().clear();
+                    }
+                    self.status.notify(&ConnectionEvent::Disconnected(err));
+                }
+                health => return health,
+            }
+        }
+    }
+
+    fn open<C: Connect>(
+        connector: &mut C,
+        config: &SupervisorConfig,
+    ) -> Result<C::Transport, Error> {
+        let mut transport = connector.connect()?;
+        transport.configure()?;
+        transport.set_timeout(Duration::from_millis(20))?;
+        for req in config.init.iter() {
+            debug!("Write init request {}", req);
+            transport.write_frame(&req.to_message())?;
+        }
+        Ok(transport)
+    }
+
+    /// Sleeps between reconnect attempts, dropping requests under the fail-fast policy.
+    fn wait_disconnected(&mut self, delay: Duration, policy: DisconnectedPolicy) {
+        let deadline = Instant::now() + delay;
+        while Instant::now() < deadline && !self.status.shutdown.load(Ordering::SeqCst) {
+            if policy == DisconnectedPolicy::FailFast {
+                while self.req_rx.try_recv().is_ok() {}
+                self.pending.lock().unwrap().clear();
+            }
+            thread::sleep(Duration::from_millis(10).min(delay));
+        }
+    }
+
+    fn finish(self, health: Health) {
+        // Wake up everyone still waiting for a response.
+        self.pending.lock().unwrap().clear();
+        self.bus.close();
+        self.status.finish(health);
+    }
+}
+
+/// Handle to the adapter. Clones share the same worker thread and can be
+/// used from several threads at once. The worker stops when the last handle
+/// is dropped.
+#[derive(Clone)]
 pub struct Mtrf {
-    join: Option<JoinHandle<()>>,
-    resp_rx: Receiver<Response>,
-    req_tx: Sender<(Request, bool)>,
+    inner: Arc<Inner>,
+    timeout: Duration,
 }
 
 impl Mtrf {
@@ -36,119 +323,816 @@ impl Mtrf {
         port_name: &str,
         on_msg: OnMsg,
     ) -> Result<Mtrf, Error> {
-        let mut port = serial::open(port_name)?;
-        port.configure(&SETTINGS)?;
-        port.set_timeout(Duration::from_millis(20))?;
-        let (resp_tx, resp_rx) = channel();
+        Self::with_transport(SerialTransport::open(port_name)?, on_msg)
+    }
 
-        let (req_tx, req_rx) = channel();
+    pub fn with_transport<T, OnMsg>(mut transport: T, on_msg: OnMsg) -> Result<Mtrf, Error>
+    where
+        T: Transport + 'static,
+        OnMsg: OnMessage + Send + 'static,
+    {
+        transport.configure()?;
+        transport.set_timeout(Duration::from_millis(20))?;
+
+        let (worker, inner) = Worker::new(on_msg, Health::Alive, DisconnectedPolicy::Queue);
+        *inner.join.lock().unwrap() = Some(Self::run_loop(transport, worker));
         Ok(Mtrf {
-            join: Some(Self::run_loop(port, req_rx, resp_tx, on_msg)),
-            resp_rx,
-            req_tx,
+            inner: Arc::new(inner),
+            timeout: DEFAULT_TIMEOUT,
         })
     }
 
-    fn run_loop<OnMsg: OnMessage + Send + 'static>(
-        mut port: SystemPort,
-        req_rx: Receiver<(Request, bool)>,
-        resp_tx: Sender<Response>,
-        mut on_msg: OnMsg,
+    /// Opens the serial port in a supervised worker, which reopens it after a
+    /// failure. The first attempt is made in the background too, so the
+    /// adapter may be plugged in later.
+    pub fn supervised<OnMsg: OnMessage + Send + 'static>(
+        port_name: &str,
+        on_msg: OnMsg,
+        config: SupervisorConfig,
+    ) -> Mtrf {
+        let port_name = port_name.to_owned();
+        Self::with_connector(move || SerialTransport::open(&port_name), on_msg, config)
+    }
+
+    pub fn with_connector<C, OnMsg>(connector: C, on_msg: OnMsg, config: SupervisorConfig) -> Mtrf
+    where
+        C: Connect + 'static,
+        OnMsg: OnMessage + Send + 'static,
+    {
+        let (mut worker, inner) = Worker::new(
+            on_msg,
+            Health::Disconnected("Not connected yet".to_owned()),
+            config.policy,
+        );
+        let join = thread::spawn(move || {
+            let health = worker.supervise(connector, config);
+            worker.finish(health);
+        });
+        *inner.join.lock().unwrap() = Some(join);
+        Mtrf {
+            inner: Arc::new(inner),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    fn run_loop<T: Transport + 'static, OnMsg: OnMessage + Send + 'static>(
+        mut transport: T,
+        mut worker: Worker<OnMsg>,
     ) -> JoinHandle<()> {
         thread::spawn(move || {
-            let mut wait_ch = None;
-            let mut msg = [0; MESSAGE_LENGTH];
-            loop {
-                match req_rx.try_recv() {
-                    Ok((req, wait_resp)) => {
-                        debug!("Write request {}", req);
-                        if wait_resp {
-                            wait_ch = Some(req.ch());
-                        }
-                        if let Err(err) = port.write_all(&req.to_message()) {
-                            warn!("Failed to write request {}", err);
-                            break;
-                        }
-                    }
-                    Err(TryRecvError::Empty) => {
-                        // no-op
+            let health = worker.session(&mut transport);
+            worker.finish(health);
+        })
+    }
+
+    pub fn send(&self, req: Request) -> Result<(), RequestError> {
+        guard(&req)?;
+        self.check_connected()?;
+        self.inner
+            .req_tx
+            .send(req)
+            .map_err(|_| RequestError::TransportClosed)
+    }
+
+    /// Sends the request and waits for the response using the default timeout.
+    pub fn send_request(&self, req: Request) -> Result<Response, RequestError> {
+        self.send_request_timeout(req, self.timeout)
+    }
+
+    pub fn send_request_timeout(
+        &self,
+        req: Request,
+        timeout: Duration,
+    ) -> Result<Response, RequestError> {
+        guard(&req)?;
+        self.request_unguarded(req, timeout)
+    }
+
+    /// `send_request_timeout` without the check for requests that clear bindings.
+    pub(crate) fn request_unguarded(
+        &self,
+        req: Request,
+        timeout: Duration,
+    ) -> Result<Response, RequestError> {
+        let (_slot, reply) = self.submit(req, PendingKey::of(&req), 1)?;
+        let resp = match reply.recv_timeout(timeout) {
+            Ok(resp) => resp,
+            Err(RecvTimeoutError::Timeout) => return Err(RequestError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => {
+                self.check_connected()?;
+                return Err(RequestError::TransportClosed);
+            }
+        };
+        check_ctr(resp)
+    }
+
+    /// Sends the request and collects the answers of every device on the
+    /// channel using the default timeout.
+    pub fn send_request_all(&self, req: Request) -> Result<Vec<Response>, RequestError> {
+        self.send_request_all_timeout(req, self.timeout)
+    }
+
+    /// Collects packets until the adapter reports none remaining (`togl == 0`)
+    /// or the timeout expires. Returns one response per device ID, in arrival
+    /// order. A device that did not answer is kept as its `NoResponse` packet,
+    /// the call fails with `DeviceNoResponse` only if none answered. Packets
+    /// collected before a timeout are returned as they are, the call only
+    /// fails with `Timeout` if none arrived.
+    pub fn send_request_all_timeout(
+        &self,
+        req: Request,
+        timeout: Duration,
+    ) -> Result<Vec<Response>, RequestError> {
+        guard(&req)?;
+        let (_slot, reply) = self.submit(req, PendingKey::collect(&req), MAX_PACKETS)?;
+        let deadline = Instant::now() + timeout;
+        let mut responses: Vec<Response> = vec![];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match reply.recv_timeout(left) {
+                Ok(resp) => {
+                    let resp = match check_ctr(resp) {
+                        Ok(resp) | Err(RequestError::DeviceNoResponse(resp)) => resp,
+                        Err(err) => return Err(err),
+                    };
+                    match responses.iter_mut().find(|known| known.id == resp.id) {
+                        Some(known) => *known = resp,
+                        None => responses.push(resp),
                     }
-                    Err(TryRecvError::Disconnected) => {
-                        info!("Request channel disconnected");
-                        break;
+                    if resp.togl == 0 {
+                        return answered(responses);
                     }
                 }
-
-                if let Ok(count) = port.read(&mut msg) {
-                    if count == 0 {
-                        thread::sleep(Duration::from_millis(10));
-                        continue;
-                    } else if count < MESSAGE_LENGTH {
-                        if let Err(err) = port.read_exact(&mut msg[count..]) {
-                            error!("Failed to read response. {}", err);
-                            break;
-                        }
-                    }
-
-                    match Response::try_from(msg) {
-                        Ok(resp) => {
-                            debug!("Receive msg:{:?}", resp);
-                            if Some(resp.ch) == wait_ch {
-                                if let Err(err) = resp_tx.send(resp) {
-                                    error!("Failed to send response: {:?}", err);
-                                    break;
-                                }
-                                wait_ch = None;
-                            } else {
-                                on_msg.on_message(resp);
-                            }
-                        }
-                        Err(err) => {
-                            warn!("Failed to decode response {}: msg:[{:?}]", err, msg);
-                        }
+                Err(RecvTimeoutError::Timeout) => {
+                    if responses.is_empty() {
+                        return Err(RequestError::Timeout);
                     }
+                    return answered(responses);
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.check_connected()?;
+                    return Err(RequestError::TransportClosed);
                 }
             }
+        }
+    }
+
+    /// Registers the reply slot and queues the request.
+    fn submit(
+        &self,
+        req: Request,
+        key: PendingKey,
+        capacity: usize,
+    ) -> Result<(Slot<'_>, Receiver<Response>), RequestError> {
+        self.check_connected()?;
+        let seq = self.inner.seq.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply) = sync_channel(capacity);
+        self.inner
+            .pending
+            .lock()
+            .unwrap()
+            .insert(seq, key, reply_tx);
+        let slot = Slot { mtrf: self, seq };
+        if self.inner.req_tx.send(req).is_err() {
+            return Err(RequestError::TransportClosed);
+        }
+        Ok((slot, reply))
+    }
+
+    /// Reads the main state (FMT 0) of the nooLite-F device bound to `ch`.
+    pub fn read_state(&self, ch: u8) -> Result<DeviceState, RequestError> {
+        self.request_state(Request {
+            mode: Mode::TxF,
+            ch,
+            cmd: Cmd::ReadState,
+            ..Default::default()
         })
     }
 
-    pub fn send(&mut self, req: Request) -> Result<(), Error> {
-        self.req_tx.send((req, false))?;
+    /// Reads the main state (FMT 0) of the nooLite-F device with the given address.
+    pub fn read_state_by_id(&self, id: u32) -> Result<DeviceState, RequestError> {
+        self.request_state(Request {
+            mode: Mode::TxF,
+            ctr: CtrRequest::SendCommandToId,
+            cmd: Cmd::ReadState,
+            id,
+            ..Default::default()
+        })
+    }
+
+    fn request_state(&self, req: Request) -> Result<DeviceState, RequestError> {
+        let resp = self.send_request(req)?;
+        match resp.payload() {
+            Payload::State(state) if resp.cmd == Cmd::SendState => Ok(state),
+            _ => Err(RequestError::UnexpectedResponse(resp)),
+        }
+    }
+
+    pub fn default_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the timeout of `send_request` for this handle. Clones keep their own value.
+    pub fn set_default_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Frame decoder counters of the read loop.
+    pub fn stats(&self) -> DecoderStats {
+        *self.inner.stats.lock().unwrap()
+    }
+
+    pub fn health(&self) -> Health {
+        self.inner.status.health.lock().unwrap().clone()
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.health() == Health::Alive
+    }
+
+    /// Queues the messages matching `filter` that answer no request, the same
+    /// ones `OnMessage` gets. The subscription ends when the worker stops.
+    pub fn subscribe(&self, filter: Filter) -> Subscription {
+        self.inner.bus.subscribe(filter)
+    }
+
+    /// Calls `handler` on a thread of its own with the messages `subscribe`
+    /// would queue, until the listener is dropped.
+    pub fn subscribe_with<F>(&self, filter: Filter, handler: F) -> Listener
+    where
+        F: FnMut(Response) + Send + 'static,
+    {
+        self.inner.bus.subscribe_with(filter, handler)
+    }
+
+    /// Registers a handler called once when the worker stops. If it has
+    /// already stopped, the handler is called right away. A supervised worker
+    /// that is reconnecting has not stopped.
+    pub fn on_disconnect<F: FnMut(&Health) + Send + 'static>(&self, mut handler: F) {
+        let mut handlers = self.inner.status.on_disconnect.lock().unwrap();
+        match handlers.as_mut() {
+            Some(handlers) => handlers.push(Box::new(handler)),
+            None => {
+                drop(handlers);
+                handler(&self.health());
+            }
+        }
+    }
+
+    /// Registers a handler for the link going down and coming back up in a
+    /// supervised worker.
+    pub fn on_connection_event<F: FnMut(&ConnectionEvent) + Send + 'static>(&self, handler: F) {
+        self.inner
+            .status
+            .on_connection
+            .lock()
+            .unwrap()
+            .push(Box::new(handler));
+    }
+
+    /// Fails fast while a supervised worker is reconnecting, if the policy says so.
+    fn check_connected(&self) -> Result<(), RequestError> {
+        if self.inner.status.policy == DisconnectedPolicy::FailFast {
+            if let Health::Disconnected(_) = self.health() {
+                return Err(RequestError::Disconnected);
+            }
+        }
         Ok(())
     }
 
-    pub fn send_request(&mut self, req: Request) -> Result<Response, Error> {
-        self.req_tx.send((req, true))?;
-        Ok(self.resp_rx.recv()?)
+    /// Stops the worker for every handle, after writing the requests already
+    /// queued, and waits for it to exit.
+    pub fn close(&self) {
+        self.inner.close();
+    }
+}
+
+/// Bindings are only cleared through [`crate::clear`], which asks for confirmation.
+/// The command is checked too, a `ClearMemory` decoded from bytes carries the key
+/// and an unknown command 132 may be given one.
+pub(crate) fn guard(req: &Request) -> Result<(), RequestError> {
+    match (req.ctr, req.cmd) {
+        (CtrRequest::ClearChannel, _)
+        | (CtrRequest::ClearMemory, _)
+        | (_, Cmd::ClearMemory(_))
+        | (_, Cmd::Unknown { cmd: 132, .. }) => Err(RequestError::InvalidRequest(
+            "Bindings are cleared with mtrf::clear",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Removes the reply slot of a request on every way out of the wait, so later
+/// answers with the same key are not routed to a receiver that is gone.
+struct Slot<'a> {
+    mtrf: &'a Mtrf,
+    seq: u64,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.mtrf.inner.pending.lock().unwrap().remove(self.seq);
+    }
+}
+
+/// Fails with `DeviceNoResponse` if no device of a collecting request answered.
+fn answered(responses: Vec<Response>) -> Result<Vec<Response>, RequestError> {
+    match responses
+        .iter()
+        .all(|resp| resp.ctr == CtrResponse::NoResponse)
+    {
+        true => Err(RequestError::DeviceNoResponse(responses[0])),
+        false => Ok(responses),
     }
 }
 
+/// Maps the adapter verdict of a response to an error.
+pub(crate) fn check_ctr(resp: Response) -> Result<Response, RequestError> {
+    match resp.ctr {
+        CtrResponse::NoResponse => Err(RequestError::DeviceNoResponse(resp)),
+        CtrResponse::Error => Err(RequestError::AdapterError(resp)),
+        CtrResponse::Success | CtrResponse::BindSuccess => Ok(resp),
+    }
+}
+
+/// Handler of the messages that answer no request. [`Mtrf::subscribe`]
+/// serves any number of consumers on top of it.
 pub trait OnMessage {
     fn on_message(&mut self, msg: Response);
+
+    /// Passes on the messages `predicate` accepts.
+    fn filter<P: FnMut(&Response) -> bool>(self, predicate: P) -> Filtered<Self, P>
+    where
+        Self: Sized,
+    {
+        Filtered::new(self, predicate)
+    }
+
+    /// Passes on the messages changed by `f`.
+    fn map<F: FnMut(Response) -> Response>(self, f: F) -> Mapped<Self, F>
+    where
+        Self: Sized,
+    {
+        Mapped::new(self, f)
+    }
+
+    /// Passes each message to this handler, then to `other`. Chain it for more handlers.
+    fn tee<O: OnMessage>(self, other: O) -> Tee<Self, O>
+    where
+        Self: Sized,
+    {
+        Tee::new(self, other)
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::cmd::request::bind;
+    use crate::bus::Filter;
+    use crate::cmd::request::{bind, set_mode, Request};
     use crate::cmd::response::Response;
-    use crate::cmd::Mode;
-    use crate::mtrf::{Mtrf, OnMessage};
+    use crate::cmd::state::{DeviceState, LoadState};
+    use crate::cmd::{Cmd, CtrRequest, CtrResponse, Mode, MESSAGE_LENGTH, RESPONSE_ST};
+    use crate::emulator::{Emulator, SimDevice};
+    use crate::error::RequestError;
+    use crate::mtrf::{Health, Mtrf, OnMessage};
     use crate::ports;
+    use crate::supervisor::{Backoff, ConnectionEvent, DisconnectedPolicy, SupervisorConfig};
+    use crate::transport::{Loopback, Transport};
+    use std::sync::mpsc::{channel, Receiver};
     use std::thread;
+    use std::time::Duration;
 
     pub struct Logger;
 
     impl OnMessage for Logger {
-        fn on_message(&self, msg: Response) {
+        fn on_message(&mut self, msg: Response) {
             println!("{}", msg);
         }
     }
 
     #[test]
+    #[ignore]
     pub fn bind_and_read() {
         let port = &ports().unwrap()[0];
-        let mut mtrf = Mtrf::new(port, Logger).unwrap();
-        mtrf.send_request(bind(Mode::RxF, 0).unwrap()).unwrap();
-        thread::sleep_ms(2000);
+        let mtrf = Mtrf::new(port, Logger).unwrap();
+        mtrf.send_request(bind(Mode::RxF, 0)).unwrap();
+        thread::sleep(Duration::from_millis(2000));
+    }
+
+    #[test]
+    pub fn request_over_loopback() {
+        let (host, mut adapter) = Loopback::pair();
+        let mtrf = Mtrf::with_transport(host, Logger).unwrap();
+
+        let adapter = thread::spawn(move || {
+            adapter.set_timeout(Duration::from_secs(1)).unwrap();
+            let mut req = [0; MESSAGE_LENGTH];
+            assert!(adapter.read_frame(&mut req).unwrap());
+            assert_eq!(req[4], 5);
+
+            let mut resp = [0; MESSAGE_LENGTH];
+            resp[0] = RESPONSE_ST;
+            resp[1] = req[1];
+            resp[2] = 3;
+            resp[4] = req[4];
+            resp[5] = 15;
+            resp[15] = resp.iter().take(15).map(|b| *b as u32).sum::<u32>() as u8;
+            resp[16] = 174;
+            adapter.write_frame(&resp).unwrap();
+        });
+
+        let resp = mtrf.send_request(bind(Mode::RX, 5)).unwrap();
+        assert_eq!(resp.ch, 5);
+        adapter.join().unwrap();
+    }
+
+    #[test]
+    pub fn read_state() {
+        let emulator = Emulator::new();
+        let mut dev = SimDevice::new(0x42);
+        dev.on = true;
+        dev.brightness = 100;
+        emulator.add_device(dev);
+        let mtrf = Mtrf::with_transport(emulator.transport(), Logger).unwrap();
+        mtrf.send_request(bind(Mode::TxF, 3)).unwrap();
+
+        let expected = DeviceState::Main {
+            device_type: 5,
+            firmware: 1,
+            state: LoadState::On,
+            brightness: 100,
+        };
+        assert_eq!(mtrf.read_state(3).unwrap(), expected);
+        assert_eq!(mtrf.read_state_by_id(0x42).unwrap(), expected);
+        match mtrf.read_state(4) {
+            Err(RequestError::DeviceNoResponse(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    pub fn collect_all_devices() {
+        let emulator = Emulator::new();
+        for id in 1..=3 {
+            emulator.add_device(SimDevice::new(id));
+        }
+        let (tx, rx) = channel();
+        let mtrf = Mtrf::with_transport(emulator.transport(), tx).unwrap();
+        for _ in 1..=3 {
+            mtrf.send_request(bind(Mode::TxF, 4)).unwrap();
+        }
+        assert_eq!(emulator.bound(Mode::TxF, 4).len(), 3);
+
+        let on = Request {
+            mode: Mode::TxF,
+            ch: 4,
+            cmd: Cmd::On,
+            ..Default::default()
+        };
+        let ids: Vec<u32> = mtrf
+            .send_request_all(on)
+            .unwrap()
+            .iter()
+            .map(|resp| resp.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        // A single response request leaves the other packets to `OnMessage`.
+        assert_eq!(mtrf.send_request(on).unwrap().togl, 2);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap().togl, 1);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap().togl, 0);
+
+        match mtrf.send_request_all(Request { ch: 5, ..on }) {
+            Err(RequestError::DeviceNoResponse(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    pub fn collect_with_silent_device() {
+        let emulator = Emulator::new();
+        emulator.add_device(SimDevice::new(1));
+        emulator.add_device(SimDevice::new(2));
+        let (tx, rx) = channel();
+        let mtrf = Mtrf::with_transport(emulator.transport(), tx).unwrap();
+        for _ in 1..=2 {
+            mtrf.send_request(bind(Mode::TxF, 6)).unwrap();
+        }
+        emulator.update_device(2, |dev| dev.online = false);
+
+        let on = Request {
+            mode: Mode::TxF,
+            ch: 6,
+            cmd: Cmd::On,
+            ..Default::default()
+        };
+        let answers: Vec<(u32, CtrResponse)> = mtrf
+            .send_request_all(on)
+            .unwrap()
+            .iter()
+            .map(|resp| (resp.id, resp.ctr))
+            .collect();
+        assert_eq!(
+            answers,
+            vec![(1, CtrResponse::Success), (2, CtrResponse::NoResponse)]
+        );
+
+        // The collecting entry is gone, the next answers go to the next request.
+        assert_eq!(mtrf.send_request_all(on).unwrap().len(), 2);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        emulator.update_device(1, |dev| dev.online = false);
+        match mtrf.send_request_all(on) {
+            Err(RequestError::DeviceNoResponse(resp)) => assert_eq!(resp.id, 1),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    pub fn resync_after_garbage() {
+        let emulator = Emulator::new();
+        let (host, mut adapter) = Loopback::pair();
+        let mtrf = Mtrf::with_transport(host, Logger).unwrap();
+
+        let relay = thread::spawn(move || {
+            let mut req = [0; MESSAGE_LENGTH];
+            adapter.set_timeout(Duration::from_secs(1)).unwrap();
+            assert!(adapter.read_frame(&mut req).unwrap());
+            emulator.handle(&req).unwrap();
+
+            let mut resp = [0; MESSAGE_LENGTH];
+            let mut transport = emulator.transport();
+            assert!(transport.read_frame(&mut resp).unwrap());
+            adapter.write_raw(&[0, RESPONSE_ST, 42]).unwrap();
+            adapter.write_raw(&resp).unwrap();
+        });
+
+        let resp = mtrf.send_request(set_mode(Mode::RX)).unwrap();
+        relay.join().unwrap();
+        assert_eq!(resp.mode, Mode::RX);
+        let stats = mtrf.stats();
+        assert_eq!(stats.resyncs, 1);
+        assert_eq!(stats.dropped_bytes, 3);
+    }
+
+    #[test]
+    pub fn late_response_after_timeout() {
+        let emulator = Emulator::new();
+        let (host, mut adapter) = Loopback::pair();
+        let mtrf = Mtrf::with_transport(host, Logger).unwrap();
+
+        let relay = thread::spawn(move || {
+            let mut transport = emulator.transport();
+            adapter.set_timeout(Duration::from_secs(1)).unwrap();
+            for delay in [100, 0].iter() {
+                let mut frame = [0; MESSAGE_LENGTH];
+                assert!(adapter.read_frame(&mut frame).unwrap());
+                emulator.handle(&frame).unwrap();
+                assert!(transport.read_frame(&mut frame).unwrap());
+                thread::sleep(Duration::from_millis(*delay));
+                adapter.write_frame(&frame).unwrap();
+            }
+        });
+
+        match mtrf.send_request_timeout(set_mode(Mode::RX), Duration::from_millis(50)) {
+            Err(RequestError::Timeout) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        thread::sleep(Duration::from_millis(200));
+
+        let on = Request {
+            mode: Mode::RX,
+            cmd: Cmd::On,
+            ..Default::default()
+        };
+        let resp = mtrf.send_request(on).unwrap();
+        assert_eq!(resp.cmd.as_u8(), Cmd::On.as_u8());
+        relay.join().unwrap();
+    }
+
+    #[test]
+    pub fn concurrent_requests() {
+        let emulator = Emulator::new();
+        emulator.add_device(SimDevice::new(1));
+        emulator.add_device(SimDevice::new(2));
+        let mtrf = Mtrf::with_transport(emulator.transport(), Logger).unwrap();
+        mtrf.send_request(bind(Mode::TxF, 4)).unwrap();
+        mtrf.send_request(bind(Mode::TxF, 4)).unwrap();
+
+        let workers: Vec<_> = vec![
+            (Mode::TxF, CtrRequest::SendCommandToId, 1),
+            (Mode::TxF, CtrRequest::SendCommandToIdInChannel, 2),
+            (Mode::RX, CtrRequest::SendCommand, 0),
+            (Mode::TX, CtrRequest::SendCommand, 0),
+        ]
+        .into_iter()
+        .map(|(mode, ctr, id)| {
+            let mtrf = mtrf.clone();
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    let req = Request {
+                        mode,
+                        ctr,
+                        ch: 4,
+                        cmd: Cmd::Switch,
+                        id,
+                    };
+                    let resp = mtrf.send_request(req).unwrap();
+                    assert_eq!(resp.mode, mode);
+                    assert_eq!(resp.id, id);
+                }
+            })
+        })
+        .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
+
+    #[test]
+    pub fn close_and_drop() {
+        let (host, mut adapter) = Loopback::pair();
+        let mtrf = Mtrf::with_transport(host, Logger).unwrap();
+        let (tx, rx) = channel();
+        mtrf.on_disconnect(move |health| tx.send(health.clone()).unwrap());
+
+        mtrf.send(set_mode(Mode::RX)).unwrap();
+        mtrf.clone().close();
+        assert_eq!(mtrf.health(), Health::Closed);
+        assert_eq!(rx.try_recv().unwrap(), Health::Closed);
+        match mtrf.send_request(set_mode(Mode::RX)) {
+            Err(RequestError::TransportClosed) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let mut frame = [0; MESSAGE_LENGTH];
+        assert!(adapter.read_frame(&mut frame).unwrap());
+
+        let (host, _adapter) = Loopback::pair();
+        let (tx, rx) = channel();
+        let mtrf = Mtrf::with_transport(host, Logger).unwrap();
+        mtrf.on_disconnect(move |health| tx.send(health.clone()).unwrap());
+        drop(mtrf);
+        assert_eq!(rx.try_recv().unwrap(), Health::Closed);
+    }
+
+    #[test]
+    pub fn subscribe_to_unsolicited() {
+        let emulator = Emulator::new();
+        let (tx, rx) = channel();
+        let mtrf = Mtrf::with_transport(emulator.transport(), tx).unwrap();
+        let switches = mtrf.subscribe(Filter::any().mode(Mode::RxF).cmd(Cmd::Switch));
+        let (pressed_tx, pressed) = channel();
+        let listener = mtrf.subscribe_with(Filter::any().id(77), move |msg| {
+            let _ = pressed_tx.send(msg);
+        });
+
+        let remote = emulator.clone();
+        let presser = thread::spawn(move || {
+            while remote.bind_mode().is_none() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            assert!(remote.press(Mode::RxF, 77, Cmd::Bind));
+        });
+        // The bind answer goes to the request, not to the subscribers.
+        mtrf.send_request(bind(Mode::RxF, 1)).unwrap();
+        presser.join().unwrap();
+
+        assert!(emulator.press(Mode::RxF, 77, Cmd::On));
+        assert!(emulator.press(Mode::RxF, 77, Cmd::Switch));
+        let timeout = Duration::from_secs(1);
+        assert_eq!(switches.recv_timeout(timeout).unwrap().ch, 1);
+        assert_eq!(pressed.recv_timeout(timeout).unwrap().cmd, Cmd::On);
+        assert_eq!(pressed.recv_timeout(timeout).unwrap().cmd, Cmd::Switch);
+        assert_eq!(rx.recv_timeout(timeout).unwrap().cmd, Cmd::On);
+        assert!(switches.try_recv().is_err());
+        assert_eq!(listener.dropped(), 0);
+
+        mtrf.close();
+        assert!(switches.recv().is_err());
+    }
+
+    #[test]
+    pub fn dead_transport() {
+        let (host, adapter) = Loopback::pair();
+        let mtrf = Mtrf::with_transport(host, Logger).unwrap();
+        assert!(mtrf.is_alive());
+        let (tx, rx) = channel();
+        mtrf.on_disconnect(move |health| tx.send(health.clone()).unwrap());
+
+        drop(adapter);
+        match rx.recv_timeout(Duration::from_secs(1)).unwrap() {
+            Health::Failed(_) => {}
+            health => panic!("Unexpected health: {}", health),
+        }
+        assert!(!mtrf.is_alive());
+
+        let (tx, rx) = channel();
+        mtrf.on_disconnect(move |health| tx.send(health.clone()).unwrap());
+        assert!(rx.try_recv().is_ok());
+    }
+
+    fn supervised(emulator: &Emulator, policy: DisconnectedPolicy) -> (Mtrf, Receiver<Response>) {
+        let config = SupervisorConfig {
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(40),
+                factor: 2,
+            },
+            init: vec![set_mode(Mode::RX)],
+            policy,
+        };
+        let (tx, rx) = channel();
+        let connector = emulator.clone();
+        let mtrf = Mtrf::with_connector(move || connector.connect(), tx, config);
+        (mtrf, rx)
+    }
+
+    #[test]
+    pub fn reconnect_fail_fast() {
+        let emulator = Emulator::new();
+        emulator.unplug();
+        let (mtrf, messages) = supervised(&emulator, DisconnectedPolicy::FailFast);
+        let (tx, events) = channel();
+        mtrf.on_connection_event(move |event| tx.send(event.clone()).unwrap());
+
+        match mtrf.send_request(set_mode(Mode::TX)) {
+            Err(RequestError::Disconnected) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        emulator.plug();
+        let event = events.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(event, ConnectionEvent::Reconnected);
+        let init = messages.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(init.mode, Mode::RX);
+        mtrf.send_request(set_mode(Mode::TX)).unwrap();
+
+        emulator.unplug();
+        match events.recv_timeout(Duration::from_secs(1)).unwrap() {
+            ConnectionEvent::Disconnected(_) => {}
+            event => panic!("Unexpected event: {}", event),
+        }
+        match mtrf.health() {
+            Health::Disconnected(_) => {}
+            health => panic!("Unexpected health: {}", health),
+        }
+
+        emulator.plug();
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(1)).unwrap(),
+            ConnectionEvent::Reconnected
+        );
+        assert!(mtrf.is_alive());
+        mtrf.close();
+        assert_eq!(mtrf.health(), Health::Closed);
+    }
+
+    #[test]
+    pub fn supervised_disconnect_handler() {
+        let emulator = Emulator::new();
+        emulator.unplug();
+        let (mtrf, _messages) = supervised(&emulator, DisconnectedPolicy::FailFast);
+        let (tx, rx) = channel();
+        mtrf.on_disconnect(move |health| tx.send(health.clone()).unwrap());
+        let (tx, events) = channel();
+        mtrf.on_connection_event(move |event| tx.send(event.clone()).unwrap());
+
+        // Waiting for the first connection and reconnecting is not the end.
+        thread::sleep(Duration::from_millis(50));
+        assert!(rx.try_recv().is_err());
+        emulator.plug();
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(1)).unwrap(),
+            ConnectionEvent::Reconnected
+        );
+        emulator.unplug();
+        match events.recv_timeout(Duration::from_secs(1)).unwrap() {
+            ConnectionEvent::Disconnected(_) => {}
+            event => panic!("Unexpected event: {}", event),
+        }
+        assert!(rx.try_recv().is_err());
+
+        mtrf.close();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(1)).unwrap(),
+            Health::Closed
+        );
+    }
+
+    #[test]
+    pub fn reconnect_queue() {
+        let emulator = Emulator::new();
+        emulator.unplug();
+        let (mtrf, _messages) = supervised(&emulator, DisconnectedPolicy::Queue);
+
+        let plug = emulator.clone();
+        let plugger = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            plug.plug();
+        });
+        let resp = mtrf.send_request(set_mode(Mode::TX)).unwrap();
+        assert_eq!(resp.mode, Mode::TX);
+        plugger.join().unwrap();
     }
 }
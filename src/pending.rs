@@ -0,0 +1,109 @@
+//! Bookkeeping of requests that wait for an adapter response.
+
+use crate::cmd::address::Address;
+use crate::cmd::request::Request;
+use crate::cmd::response::Response;
+use crate::cmd::Mode;
+
+/// What a response has to carry to answer a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PendingKey {
+    mode: Mode,
+    /// `None` for commands addressed by ID only, the channel is irrelevant there.
+    ch: Option<u8>,
+    /// Set for nooLite-F commands addressed to a device ID.
+    id: Option<u32>,
+    /// Keeps the slot until the packet with `togl == 0`, to gather the
+    /// answers of every device on the channel.
+    collect: bool,
+}
+
+impl PendingKey {
+    pub(crate) fn of(req: &Request) -> PendingKey {
+        let address = Address::of(req);
+        PendingKey {
+            mode: req.mode,
+            ch: address.ch(),
+            id: address.id(),
+            collect: false,
+        }
+    }
+
+    /// Key of a request that waits for the answers of several devices.
+    pub(crate) fn collect(req: &Request) -> PendingKey {
+        PendingKey {
+            collect: true,
+            ..PendingKey::of(req)
+        }
+    }
+
+    fn matches(&self, resp: &Response) -> bool {
+        self.mode == resp.mode
+            && self.ch.map(|ch| ch == resp.ch).unwrap_or(true)
+            && self.id.map(|id| id == resp.id).unwrap_or(true)
+    }
+}
+
+struct Pending<S> {
+    seq: u64,
+    key: PendingKey,
+    reply: S,
+}
+
+/// Requests awaiting a response, oldest first. `S` is the reply slot.
+pub(crate) struct PendingTable<S> {
+    entries: Vec<Pending<S>>,
+}
+
+impl<S> Default for PendingTable<S> {
+    fn default() -> Self {
+        PendingTable { entries: vec![] }
+    }
+}
+
+impl<S> PendingTable<S> {
+    pub(crate) fn insert(&mut self, seq: u64, key: PendingKey, reply: S) {
+        self.entries.push(Pending { seq, key, reply });
+    }
+
+    pub(crate) fn remove(&mut self, seq: u64) {
+        self.entries.retain(|pending| pending.seq != seq);
+    }
+
+    /// Drops every reply slot, which wakes up the waiting callers.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Takes the reply slot of the oldest request the response answers.
+    /// Requests addressed to a device ID win over plain channel requests.
+    #[cfg(feature = "async")]
+    pub(crate) fn take(&mut self, resp: &Response) -> Option<S> {
+        let idx = self.find(resp)?;
+        Some(self.entries.remove(idx).reply)
+    }
+
+    fn find(&self, resp: &Response) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|pending| pending.key.id.is_some() && pending.key.matches(resp))
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .position(|pending| pending.key.matches(resp))
+            })
+    }
+}
+
+impl<S: Clone> PendingTable<S> {
+    /// Same as `take`, but collecting requests keep their slot while more
+    /// packets are announced by `togl`.
+    pub(crate) fn route(&mut self, resp: &Response) -> Option<S> {
+        let idx = self.find(resp)?;
+        if self.entries[idx].key.collect && resp.togl > 0 {
+            Some(self.entries[idx].reply.clone())
+        } else {
+            Some(self.entries.remove(idx).reply)
+        }
+    }
+}
@@ -0,0 +1,395 @@
+//! Record of what is bound where in the adapter memory.
+//!
+//! The adapter cannot list its bind table, so the registry keeps a copy on
+//! disk, updated from the answers to bind and unbind requests. Reading and
+//! writing the file needs the `serde` feature.
+
+use std::fmt;
+#[cfg(feature = "serde")]
+use std::fs;
+#[cfg(feature = "serde")]
+use std::io;
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+use anyhow::Error;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::cmd::request::Request;
+use crate::cmd::response::Response;
+use crate::cmd::{Cmd, CtrRequest, CtrResponse, Mode, CHANNELS};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DeviceKind {
+    Relay,
+    Dimmer,
+    Rgb,
+    Remote,
+    Sensor,
+    Other,
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceKind::Relay => write!(f, "relay"),
+            DeviceKind::Dimmer => write!(f, "dimmer"),
+            DeviceKind::Rgb => write!(f, "rgb"),
+            DeviceKind::Remote => write!(f, "remote"),
+            DeviceKind::Sensor => write!(f, "sensor"),
+            DeviceKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeviceEntry {
+    pub name: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub room: Option<String>,
+    pub kind: DeviceKind,
+    /// Stored by [`Mode::name`], e.g. `"txf"`.
+    pub mode: Mode,
+    pub ch: u8,
+    /// Address of a nooLite-F device, `None` in the nooLite modes.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub id: Option<u32>,
+}
+
+impl DeviceEntry {
+    /// `room.name`, or just the name for devices without a room.
+    pub fn full_name(&self) -> String {
+        match &self.room {
+            Some(room) => format!("{}.{}", room, self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+impl fmt::Display for DeviceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) {}:{}",
+            self.full_name(),
+            self.kind,
+            self.mode,
+            self.ch
+        )?;
+        if let Some(id) = self.id {
+            write!(f, " id:{:#010x}", id)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Registry {
+    #[cfg_attr(feature = "serde", serde(default))]
+    devices: Vec<DeviceEntry>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Reads the registry from a `.toml` or JSON file. A missing file is an
+    /// empty registry.
+    #[cfg(feature = "serde")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Registry, Error> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Registry::new()),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(if is_toml(path) {
+            toml::from_str(&text)?
+        } else {
+            serde_json::from_str(&text)?
+        })
+    }
+
+    /// Writes the registry as TOML if the file name ends with `.toml`, as JSON otherwise.
+    #[cfg(feature = "serde")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let text = if is_toml(path) {
+            toml::to_string_pretty(self)?
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Adds a device bound by other means. The full name must be unique.
+    pub fn add(&mut self, entry: DeviceEntry) -> Result<(), Error> {
+        ensure!(entry.ch < CHANNELS, "Invalid channel:{}", entry.ch);
+        let name = entry.full_name();
+        ensure!(
+            self.device(&name).is_none(),
+            "Device {} is already registered",
+            name
+        );
+        self.devices.push(entry);
+        Ok(())
+    }
+
+    /// Records the device bound by `req` if `resp` confirms the bind:
+    /// `BindSuccess` in the RX modes, `Success` in the TX modes.
+    ///
+    /// `name` is `room.name` or a bare name.
+    pub fn record_bind(
+        &mut self,
+        req: &Request,
+        resp: &Response,
+        name: &str,
+        kind: DeviceKind,
+    ) -> Result<&DeviceEntry, Error> {
+        ensure!(req.cmd == Cmd::Bind, "Not a bind request: {}", req);
+        let confirmed = match req.mode {
+            Mode::RX | Mode::RxF => CtrResponse::BindSuccess,
+            _ => CtrResponse::Success,
+        };
+        ensure!(
+            resp.ctr == confirmed && resp.mode == req.mode && resp.ch == req.ch,
+            "The bind did not succeed: {}",
+            resp
+        );
+        let id = match resp.mode {
+            Mode::TxF | Mode::RxF => Some(resp.id),
+            _ => None,
+        };
+        let (room, name) = match name.find('.') {
+            Some(idx) => (Some(name[..idx].to_owned()), name[idx + 1..].to_owned()),
+            None => (None, name.to_owned()),
+        };
+        self.add(DeviceEntry {
+            name,
+            room,
+            kind,
+            mode: resp.mode,
+            ch: resp.ch,
+            id,
+        })?;
+        Ok(self.devices.last().unwrap())
+    }
+
+    /// Forgets the devices removed by `req` if `resp` confirms it. Returns the
+    /// removed entries.
+    pub fn record_unbind(&mut self, req: &Request, resp: &Response) -> Vec<DeviceEntry> {
+        if resp.ctr != CtrResponse::Success || resp.mode != req.mode {
+            return vec![];
+        }
+        let (mode, ch, id) = (req.mode, req.ch, req.id);
+        match req.ctr {
+            CtrRequest::UnbindAddressFromChannel => {
+                self.remove(|dev| dev.mode == mode && dev.ch == ch && matches_id(dev, id))
+            }
+            CtrRequest::ClearChannel => self.remove(|dev| dev.mode == mode && dev.ch == ch),
+            CtrRequest::ClearMemory => self.remove(|dev| dev.mode == mode),
+            _ if mode == Mode::TxF && req.cmd == Cmd::Unbind => {
+                // The device answers the unbind itself and leaves every channel.
+                let id = resp.id;
+                self.remove(|dev| dev.mode == mode && dev.id == Some(id))
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Looks a device up by `room.name`, or by name for devices without a room.
+    pub fn device(&self, name: &str) -> Option<&DeviceEntry> {
+        self.devices.iter().find(|dev| dev.full_name() == name)
+    }
+
+    pub fn by_id(&self, id: u32) -> Option<&DeviceEntry> {
+        self.devices.iter().find(|dev| dev.id == Some(id))
+    }
+
+    pub fn devices(&self) -> &[DeviceEntry] {
+        &self.devices
+    }
+
+    pub fn in_channel(&self, mode: Mode, ch: u8) -> impl Iterator<Item = &DeviceEntry> {
+        self.devices
+            .iter()
+            .filter(move |dev| dev.mode == mode && dev.ch == ch)
+    }
+
+    pub fn in_room<'a>(&'a self, room: &'a str) -> impl Iterator<Item = &'a DeviceEntry> {
+        self.devices
+            .iter()
+            .filter(move |dev| dev.room.as_deref() == Some(room))
+    }
+
+    /// Channels of `mode` with nothing registered, in ascending order.
+    pub fn free_channels(&self, mode: Mode) -> Vec<u8> {
+        (0..CHANNELS)
+            .filter(|ch| self.in_channel(mode, *ch).next().is_none())
+            .collect()
+    }
+
+    pub fn free_channel(&self, mode: Mode) -> Option<u8> {
+        self.free_channels(mode).into_iter().next()
+    }
+
+    fn remove<F: Fn(&DeviceEntry) -> bool>(&mut self, f: F) -> Vec<DeviceEntry> {
+        let (removed, kept) = self.devices.drain(..).partition(|dev| f(dev));
+        self.devices = kept;
+        removed
+    }
+}
+
+/// Unbind by channel in nooLite modes, by channel and ID in nooLite-F modes.
+fn matches_id(dev: &DeviceEntry, id: u32) -> bool {
+    dev.id.map(|dev_id| dev_id == id).unwrap_or(true)
+}
+
+#[cfg(feature = "serde")]
+fn is_toml(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "toml").unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "serde")]
+    use std::path::PathBuf;
+    #[cfg(feature = "serde")]
+    use std::{env, fs, process};
+
+    use crate::cmd::request::{bind, Request};
+    use crate::cmd::response::Response;
+    use crate::cmd::{Cmd, CtrRequest, CtrResponse, Mode};
+    #[cfg(feature = "serde")]
+    use crate::registry::DeviceEntry;
+    use crate::registry::{DeviceKind, Registry};
+
+    fn answer(req: &Request, ctr: CtrResponse, id: u32) -> Response {
+        Response {
+            mode: req.mode,
+            ctr,
+            togl: 0,
+            ch: req.ch,
+            cmd: req.cmd,
+            fmt: 0,
+            data: [0; 4],
+            id,
+            crc: 0,
+        }
+    }
+
+    #[test]
+    pub fn bind_and_unbind() {
+        let mut registry = Registry::new();
+        let req = bind(Mode::TxF, 0);
+        registry
+            .record_bind(
+                &req,
+                &answer(&req, CtrResponse::Success, 7),
+                "kitchen.ceiling",
+                DeviceKind::Dimmer,
+            )
+            .unwrap();
+        let req = bind(Mode::RX, 0);
+        assert!(registry
+            .record_bind(
+                &req,
+                &answer(&req, CtrResponse::NoResponse, 0),
+                "hall.switch",
+                DeviceKind::Remote
+            )
+            .is_err());
+        registry
+            .record_bind(
+                &req,
+                &answer(&req, CtrResponse::BindSuccess, 0),
+                "hall.switch",
+                DeviceKind::Remote,
+            )
+            .unwrap();
+
+        let ceiling = registry.device("kitchen.ceiling").unwrap();
+        assert_eq!(ceiling.id, Some(7));
+        assert_eq!(registry.by_id(7).unwrap().name, "ceiling");
+        assert_eq!(registry.device("hall.switch").unwrap().id, None);
+        assert_eq!(registry.free_channel(Mode::TxF), Some(1));
+        assert_eq!(registry.free_channels(Mode::RX).len(), 63);
+
+        let clear = Request {
+            mode: Mode::RX,
+            ctr: CtrRequest::ClearChannel,
+            ..Default::default()
+        };
+        let removed = registry.record_unbind(&clear, &answer(&clear, CtrResponse::Success, 0));
+        assert_eq!(removed.len(), 1);
+        let unbind = Request {
+            mode: Mode::TxF,
+            cmd: Cmd::Unbind,
+            ..Default::default()
+        };
+        registry.record_unbind(&unbind, &answer(&unbind, CtrResponse::Success, 7));
+        assert!(registry.devices().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    /// File in the temp dir that no other test or test run uses.
+    fn temp_path(test: &str, ext: &str) -> PathBuf {
+        env::temp_dir().join(format!("mtrf-registry-{}-{}.{}", process::id(), test, ext))
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn save_and_load() {
+        let mut registry = Registry::new();
+        let req = bind(Mode::RxF, 12);
+        registry
+            .record_bind(
+                &req,
+                &answer(&req, CtrResponse::BindSuccess, 0xABCD),
+                "porch.motion",
+                DeviceKind::Sensor,
+            )
+            .unwrap();
+
+        for ext in &["toml", "json"] {
+            let path = temp_path("save_and_load", ext);
+            registry.save(&path).unwrap();
+            assert_eq!(Registry::load(&path).unwrap(), registry);
+            fs::remove_file(&path).unwrap();
+        }
+        let missing = temp_path("save_and_load-missing", "json");
+        assert!(Registry::load(missing).unwrap().devices().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn mode_names() {
+        let entry = DeviceEntry {
+            name: "lamp".to_owned(),
+            room: None,
+            kind: DeviceKind::Relay,
+            mode: Mode::TxF,
+            ch: 3,
+            id: Some(7),
+        };
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["mode"], "txf");
+        assert_eq!(serde_json::from_value::<DeviceEntry>(json).unwrap(), entry);
+    }
+}
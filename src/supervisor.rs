@@ -0,0 +1,67 @@
+//! Settings of a supervised connection, which reopens the port after it fails.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::cmd::request::Request;
+
+/// Delay between reconnect attempts, growing from `initial` up to `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub factor: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            factor: 2,
+        }
+    }
+}
+
+impl Backoff {
+    pub fn next(&self, delay: Duration) -> Duration {
+        (delay * self.factor).min(self.max)
+    }
+}
+
+/// What happens to requests made while the adapter is disconnected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisconnectedPolicy {
+    /// Keep them and write them once the port is back. Callers still wait
+    /// no longer than their timeout.
+    #[default]
+    Queue,
+    /// Fail them right away with `RequestError::Disconnected`.
+    FailFast,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SupervisorConfig {
+    pub backoff: Backoff,
+    /// Written after every (re)connect, e.g. mode setup. Their answers are
+    /// delivered to `OnMessage`.
+    pub init: Vec<Request>,
+    pub policy: DisconnectedPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The link failed, the supervisor is reconnecting.
+    Disconnected(String),
+    /// The link is up again, the init sequence has been written.
+    Reconnected,
+}
+
+impl fmt::Display for ConnectionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionEvent::Disconnected(err) => write!(f, "Disconnected({})", err),
+            ConnectionEvent::Reconnected => write!(f, "Reconnected"),
+        }
+    }
+}
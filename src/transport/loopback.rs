@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+
+use crate::cmd::MESSAGE_LENGTH;
+use crate::transport::Transport;
+
+#[derive(Default)]
+struct Pipe {
+    buf: Mutex<PipeState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn push(&self, data: &[u8]) -> io::Result<()> {
+        let mut state = self.buf.lock().unwrap();
+        if state.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Loopback peer dropped",
+            ));
+        }
+        state.bytes.extend(data.iter());
+        self.ready.notify_all();
+        Ok(())
+    }
+
+    fn pop(&self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.buf.lock().unwrap();
+        loop {
+            if !state.bytes.is_empty() {
+                let count = buf.len().min(state.bytes.len());
+                for (dst, src) in buf.iter_mut().zip(state.bytes.drain(..count)) {
+                    *dst = src;
+                }
+                return Ok(count);
+            }
+            if state.closed {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "Loopback peer dropped",
+                ));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(0);
+            }
+            state = self.ready.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    fn close(&self) {
+        self.buf.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// In-memory transport. Frames written to one end of a pair are read from the other.
+pub struct Loopback {
+    rx: Arc<Pipe>,
+    tx: Arc<Pipe>,
+    timeout: Duration,
+}
+
+impl Loopback {
+    pub fn pair() -> (Loopback, Loopback) {
+        let a = Arc::new(Pipe::default());
+        let b = Arc::new(Pipe::default());
+        (
+            Loopback {
+                rx: a.clone(),
+                tx: b.clone(),
+                timeout: Duration::from_millis(20),
+            },
+            Loopback {
+                rx: b,
+                tx: a,
+                timeout: Duration::from_millis(20),
+            },
+        )
+    }
+
+    /// Writes raw bytes to the peer, bypassing frame boundaries.
+    pub fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        self.tx.push(data)
+    }
+}
+
+impl Transport for Loopback {
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.rx.pop(buf, self.timeout)
+    }
+
+    fn write_frame(&mut self, frame: &[u8; MESSAGE_LENGTH]) -> io::Result<()> {
+        self.tx.push(frame)
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        self.rx.close();
+        self.tx.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::transport::{Loopback, Transport};
+
+    #[test]
+    pub fn frames_cross_the_pair() {
+        let (mut a, mut b) = Loopback::pair();
+        let frame = [7; 17];
+        a.write_frame(&frame).unwrap();
+
+        let mut buf = [0; 17];
+        assert!(b.read_frame(&mut buf).unwrap());
+        assert_eq!(frame, buf);
+
+        b.set_timeout(Duration::from_millis(1)).unwrap();
+        assert!(!b.read_frame(&mut buf).unwrap());
+
+        drop(a);
+        assert!(b.read_frame(&mut buf).is_err());
+    }
+}
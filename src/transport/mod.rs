@@ -0,0 +1,106 @@
+use std::io;
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::cmd::MESSAGE_LENGTH;
+
+pub mod loopback;
+pub mod serial;
+pub mod tcp;
+
+pub use self::loopback::Loopback;
+pub use self::serial::SerialTransport;
+pub use self::tcp::TcpTransport;
+
+/// Byte pipe between the driver and an MTRF-64 adapter.
+///
+/// Implementations must return `Ok(0)` from `read` when the read timeout
+/// expires without data, and an error once the underlying link is gone.
+pub trait Transport: Send {
+    /// Applies the link settings (baud rate, framing, etc.) if the transport has any.
+    fn configure(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error>;
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn write_frame(&mut self, frame: &[u8; MESSAGE_LENGTH]) -> io::Result<()>;
+
+    /// Reads one whole frame. Returns `false` if no data arrived before the timeout.
+    fn read_frame(&mut self, frame: &mut [u8; MESSAGE_LENGTH]) -> io::Result<bool> {
+        let count = self.read(frame)?;
+        if count == 0 {
+            return Ok(false);
+        }
+
+        let mut filled = count;
+        while filled < MESSAGE_LENGTH {
+            match self.read(&mut frame[filled..])? {
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Timed out in the middle of a frame",
+                    ))
+                }
+                count => filled += count,
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn configure(&mut self) -> Result<(), Error> {
+        (**self).configure()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        (**self).set_timeout(timeout)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn write_frame(&mut self, frame: &[u8; MESSAGE_LENGTH]) -> io::Result<()> {
+        (**self).write_frame(frame)
+    }
+
+    fn read_frame(&mut self, frame: &mut [u8; MESSAGE_LENGTH]) -> io::Result<bool> {
+        (**self).read_frame(frame)
+    }
+}
+
+/// Opens a fresh transport, used to reconnect after the previous one failed.
+pub trait Connect: Send {
+    type Transport: Transport + 'static;
+
+    fn connect(&mut self) -> Result<Self::Transport, Error>;
+}
+
+impl<T, F> Connect for F
+where
+    T: Transport + 'static,
+    F: FnMut() -> Result<T, Error> + Send,
+{
+    type Transport = T;
+
+    fn connect(&mut self) -> Result<T, Error> {
+        self()
+    }
+}
+
+/// Maps read timeouts to `Ok(0)` as required by [`Transport::read`].
+fn timeout_as_empty(res: io::Result<usize>) -> io::Result<usize> {
+    match res {
+        Err(err)
+            if err.kind() == io::ErrorKind::TimedOut || err.kind() == io::ErrorKind::WouldBlock =>
+        {
+            Ok(0)
+        }
+        res => res,
+    }
+}
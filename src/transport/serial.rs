@@ -0,0 +1,54 @@
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use ::serial::core::BaudRate::Baud9600;
+use ::serial::core::CharSize::Bits8;
+use ::serial::core::FlowControl::FlowNone;
+use ::serial::core::Parity::ParityNone;
+use ::serial::core::StopBits::Stop1;
+use ::serial::{PortSettings, SerialPort, SystemPort};
+use anyhow::Error;
+
+use crate::cmd::MESSAGE_LENGTH;
+use crate::transport::{timeout_as_empty, Transport};
+
+pub const SETTINGS: PortSettings = PortSettings {
+    baud_rate: Baud9600,
+    char_size: Bits8,
+    parity: ParityNone,
+    stop_bits: Stop1,
+    flow_control: FlowNone,
+};
+
+/// Local tty the adapter is plugged into.
+pub struct SerialTransport {
+    port: SystemPort,
+}
+
+impl SerialTransport {
+    pub fn open(port_name: &str) -> Result<SerialTransport, Error> {
+        Ok(SerialTransport {
+            port: ::serial::open(port_name)?,
+        })
+    }
+}
+
+impl Transport for SerialTransport {
+    fn configure(&mut self) -> Result<(), Error> {
+        self.port.configure(&SETTINGS)?;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.port.set_timeout(timeout)?;
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        timeout_as_empty(self.port.read(buf))
+    }
+
+    fn write_frame(&mut self, frame: &[u8; MESSAGE_LENGTH]) -> io::Result<()> {
+        self.port.write_all(frame)
+    }
+}
@@ -0,0 +1,47 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::cmd::MESSAGE_LENGTH;
+use crate::transport::{timeout_as_empty, Transport};
+
+/// Raw TCP connection to a network serial bridge (ser2net, ESP-Link, etc.).
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<TcpTransport, Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport { stream })
+    }
+
+    pub fn from_stream(stream: TcpStream) -> TcpTransport {
+        TcpTransport { stream }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buf) {
+            // Timeouts come back as errors, so a zero-length read is EOF.
+            Ok(0) if !buf.is_empty() => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed by peer",
+            )),
+            res => timeout_as_empty(res),
+        }
+    }
+
+    fn write_frame(&mut self, frame: &[u8; MESSAGE_LENGTH]) -> io::Result<()> {
+        self.stream.write_all(frame)
+    }
+}